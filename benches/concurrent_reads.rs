@@ -0,0 +1,62 @@
+//! Compares the read throughput of the caches when many tasks read from the
+//! same shard at once. Run with `cargo bench --bench concurrent_reads`.
+
+use cache::cache::{clock::ClockCache, lru::LRUCache, sharded::ShardedCache, Cache};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+const KEYS: usize = 10_000;
+const TASKS: usize = 16;
+const READS_PER_TASK: usize = 100_000;
+
+async fn bench_reads<C>(name: &str)
+where
+    C: Cache<String, String> + Send + Sync + 'static,
+{
+    let mut cache = C::new(KEYS);
+    for key in 0..KEYS {
+        cache.put(key.to_string(), key.to_string()).unwrap();
+    }
+    // A single shard, so that every read contends on the same lock
+    let cache = Arc::new(ShardedCache::new(vec![cache]));
+    let keys: Arc<Vec<String>> = Arc::new((0..KEYS).map(|key| key.to_string()).collect());
+
+    let start = Instant::now();
+    let tasks: Vec<_> = (0..TASKS)
+        .map(|task| {
+            let cache = Arc::clone(&cache);
+            let keys = Arc::clone(&keys);
+            tokio::spawn(async move {
+                let mut hits = 0;
+                for read in 0..READS_PER_TASK {
+                    let key = &keys[(task * 7919 + read * 31) % KEYS];
+                    if cache.get(key).await.is_some() {
+                        hits += 1;
+                    }
+                }
+                hits
+            })
+        })
+        .collect();
+    let mut hits = 0;
+    for task in tasks {
+        hits += task.await.unwrap();
+    }
+    report(name, hits, start.elapsed());
+}
+
+fn report(name: &str, hits: usize, elapsed: Duration) {
+    let reads = TASKS * READS_PER_TASK;
+    println!(
+        "{name:>6}: {reads} reads ({hits} hits) by {TASKS} tasks in {elapsed:?}, {:.0} reads/s",
+        reads as f64 / elapsed.as_secs_f64()
+    );
+}
+
+#[tokio::main]
+async fn main() {
+    bench_reads::<LRUCache<String, String>>("lru").await;
+    bench_reads::<ClockCache<String, String>>("clock").await;
+}
@@ -32,6 +32,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     bindings.write_to_file(out_dir.join("bindings.rs"))?;
 
     tonic_build::configure()
+        .bytes([
+            ".api.Value",
+            ".api.PublishRequest.message",
+            ".api.ChannelMessage.message",
+        ])
         .compile(&["protos/cache.proto", "protos/cluster.proto"], &["protos"])?;
     Ok(())
 }
@@ -1,19 +1,34 @@
+use bytes::Bytes;
 use cache::{
-    cache::{lru::LRUCache, Cache},
-    CacheServer,
+    cache::{
+        arc::ArcCache, clock::ClockCache, lfu::LFUCache, lru::LRUCache, s3_fifo::S3FIFOCache,
+        sharded::ShardedCache, tiny_lfu::TinyLFUCache, Cache, MemoryWeigher, Versioned,
+    },
+    CacheServer, HTTPServer, MemcachedServer, RESPServer, RPCServer,
 };
 use clap::{Parser, ValueEnum};
+use std::error::Error;
 use std::fmt::Display;
+use std::{num::NonZeroUsize, thread};
 
 #[derive(Debug, Clone, ValueEnum)]
 enum ServerType {
     Grpc,
+    /// REST API over HTTP
+    Http,
+    /// Redis protocol (RESP2 and RESP3)
+    Resp,
+    /// Memcached text and binary protocols
+    Memcached,
 }
 
 impl Display for ServerType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Grpc => f.write_str("grpc"),
+            Self::Http => f.write_str("http"),
+            Self::Resp => f.write_str("resp"),
+            Self::Memcached => f.write_str("memcached"),
         }
     }
 }
@@ -21,16 +36,48 @@ impl Display for ServerType {
 #[derive(Debug, Clone, ValueEnum)]
 enum CacheType {
     Lru,
+    Lfu,
+    TinyLfu,
+    Arc,
+    S3Fifo,
+    Clock,
 }
 
 impl Display for CacheType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Lru => f.write_str("lru"),
+            Self::Lfu => f.write_str("lfu"),
+            Self::TinyLfu => f.write_str("tiny-lfu"),
+            Self::Arc => f.write_str("arc"),
+            Self::S3Fifo => f.write_str("s3-fifo"),
+            Self::Clock => f.write_str("clock"),
         }
     }
 }
 
+/// Parses a memory size like `512MB` into bytes, using binary multiples.
+fn parse_memory(size: &str) -> Result<usize, String> {
+    let size = size.trim().to_ascii_uppercase();
+    let digits = size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(digits);
+    let number: usize = number
+        .parse()
+        .map_err(|_| format!("`{size}` does not start with a number"))?;
+    let multiplier: usize = match unit.trim() {
+        "" | "B" => 1,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        unit => return Err(format!("unknown memory unit `{unit}`")),
+    };
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("`{size}` is too large"))
+}
+
 #[derive(Parser, Debug)]
 #[command(author="Subhradeep Chakraborty", version, about, long_about = None)]
 /// Fast, asynchronous cache server
@@ -41,6 +88,13 @@ struct Args {
     #[arg(short, long, default_value_t = 100)]
     capacity: usize,
 
+    /// Bounds the cache by the bytes taken by the entries (e.g. 512MB)
+    /// instead of the number of entries. The budget is split evenly among
+    /// the shards, so the largest entry the cache can hold takes
+    /// max-memory / shards bytes
+    #[arg(long, value_parser = parse_memory)]
+    max_memory: Option<usize>,
+
     #[arg(long, default_value_t = String::from("127.0.0.1"))]
     host: String,
 
@@ -49,19 +103,87 @@ struct Args {
 
     #[arg(long, default_value_t = CacheType::Lru)]
     cache: CacheType,
+
+    /// Number of independently locked shards the capacity is split over,
+    /// defaults to the number of CPU cores and is lowered so that every
+    /// shard gets a capacity of at least 2
+    #[arg(long)]
+    shards: Option<usize>,
+}
+
+/// Builds the shards of the cache, splitting the capacity bounded either by
+/// the number of entries or by memory among them.
+fn build_cache<C>(args: &Args) -> ShardedCache<C>
+where
+    C: Cache<Vec<u8>, Versioned<Bytes>>,
+{
+    let budget = args.max_memory.unwrap_or(args.capacity);
+    // Every shard gets a capacity of at least 2, as the policies splitting
+    // their capacity into several queues fall back to a default one below
+    // that
+    let shards = args
+        .shards
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, NonZeroUsize::get))
+        .min(budget / 2)
+        .max(1);
+    let caches = (0..shards)
+        .map(|_| match args.max_memory {
+            Some(max_memory) => {
+                C::with_weigher(max_memory.div_ceil(shards), Box::new(MemoryWeigher))
+            }
+            None => C::new(args.capacity.div_ceil(shards)),
+        })
+        .collect();
+    ShardedCache::new(caches)
+}
+
+/// Serves the cache over the protocol of the given server type.
+async fn serve<C>(
+    server: &ServerType,
+    addr: &str,
+    cache: ShardedCache<C>,
+) -> Result<(), Box<dyn Error>>
+where
+    C: Cache<Vec<u8>, Versioned<Bytes>> + Send + Sync + 'static,
+{
+    match server {
+        ServerType::Grpc => CacheServer::<C, RPCServer>::run(addr, cache).await,
+        ServerType::Http => CacheServer::<C, HTTPServer>::run(addr, cache).await,
+        ServerType::Resp => CacheServer::<C, RESPServer>::run(addr, cache).await,
+        ServerType::Memcached => CacheServer::<C, MemcachedServer>::run(addr, cache).await,
+    }
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() -> Result<(), Box<dyn Error>> {
     let args = Args::parse();
 
-    match args.server {
-        ServerType::Grpc => {
-            let cache = LRUCache::<String, String>::new(args.capacity);
-            let addr = format!("{host}:{port}", host = args.host, port = args.port);
-            CacheServer::run(addr.as_str(), cache).await?;
+    let addr = format!("{host}:{port}", host = args.host, port = args.port);
+    let server = &args.server;
+    match args.cache {
+        CacheType::Lru => {
+            let cache: ShardedCache<LRUCache<Vec<u8>, Versioned<Bytes>>> = build_cache(&args);
+            serve(server, &addr, cache).await
+        }
+        CacheType::Lfu => {
+            let cache: ShardedCache<LFUCache<Vec<u8>, Versioned<Bytes>>> = build_cache(&args);
+            serve(server, &addr, cache).await
+        }
+        CacheType::TinyLfu => {
+            let cache: ShardedCache<TinyLFUCache<Vec<u8>, Versioned<Bytes>>> = build_cache(&args);
+            serve(server, &addr, cache).await
+        }
+        CacheType::Arc => {
+            let cache: ShardedCache<ArcCache<Vec<u8>, Versioned<Bytes>>> = build_cache(&args);
+            serve(server, &addr, cache).await
+        }
+        CacheType::S3Fifo => {
+            let cache: ShardedCache<S3FIFOCache<Vec<u8>, Versioned<Bytes>>> = build_cache(&args);
+            serve(server, &addr, cache).await
+        }
+        CacheType::Clock => {
+            let cache: ShardedCache<ClockCache<Vec<u8>, Versioned<Bytes>>> = build_cache(&args);
+            serve(server, &addr, cache).await
         }
     }
-
-    Ok(())
 }
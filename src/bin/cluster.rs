@@ -1,5 +1,9 @@
-use cache::RPCServer;
-use cache::{network::CacheNetwork, CacheClusterServer};
+use cache::network::{
+    router::{JumpHash, Ketama, Maglev, Rendezvous, Router, DEFAULT_VIRTUAL_NODES},
+    CacheNetwork, ServerNode,
+};
+use cache::CacheClusterServer;
+use cache::{HTTPServer, RPCServer};
 use clap::{Parser, ValueEnum};
 use std::fmt::Display;
 use std::vec::Vec;
@@ -7,16 +11,60 @@ use std::vec::Vec;
 #[derive(Debug, Clone, ValueEnum)]
 enum ServerType {
     Grpc,
+    Http,
 }
 
 impl Display for ServerType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Grpc => f.write_str("grpc"),
+            Self::Http => f.write_str("http"),
         }
     }
 }
 
+#[derive(Debug, Clone, ValueEnum)]
+enum RoutingType {
+    /// Weighted rendezvous hashing
+    Rendezvous,
+    /// Consistent hash ring compatible with ketama
+    Ketama,
+    /// Jump consistent hash
+    Jump,
+    /// Maglev lookup table
+    Maglev,
+}
+
+impl Display for RoutingType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Rendezvous => f.write_str("rendezvous"),
+            Self::Ketama => f.write_str("ketama"),
+            Self::Jump => f.write_str("jump"),
+            Self::Maglev => f.write_str("maglev"),
+        }
+    }
+}
+
+/// Parses a node given as `host:port`, optionally followed by `@weight`,
+/// where the host must resolve.
+fn parse_node(node: &str) -> Result<(String, usize), String> {
+    let (addr, weight) = match node.rsplit_once('@') {
+        Some((addr, weight)) => {
+            let weight = weight
+                .parse()
+                .map_err(|_| format!("`{weight}` is not a valid weight"))?;
+            (addr, weight)
+        }
+        None => (node, 1),
+    };
+    if weight == 0 {
+        return Err("the weight of a node must be at least 1".to_string());
+    }
+    ServerNode::parse(addr, weight).map_err(|_| format!("`{addr}` is not a valid address"))?;
+    Ok((addr.to_string(), weight))
+}
+
 #[derive(Debug, Parser)]
 #[command(author="Subhradeep Chakraborty", version, about, long_about = None)]
 /// CLI to configure and manage the cluster of Cache nodes
@@ -30,8 +78,36 @@ struct Args {
     #[arg(short, long, default_value_t = 7000)]
     port: u16,
 
-    #[arg(short, long)]
-    nodes: Vec<String>,
+    /// Also serves the HTTP API on this port, next to the gRPC server
+    #[arg(long)]
+    http_port: Option<u16>,
+
+    /// Cache nodes as `host:port`, optionally followed by `@weight` so that
+    /// larger nodes receive proportionally more keys (defaults to 1)
+    #[arg(short, long, value_parser = parse_node)]
+    nodes: Vec<(String, usize)>,
+
+    /// How the keys are spread over the nodes
+    #[arg(long, default_value_t = RoutingType::Rendezvous)]
+    routing: RoutingType,
+
+    /// Points of a node of weight 1 on the ketama ring
+    #[arg(long, default_value_t = DEFAULT_VIRTUAL_NODES)]
+    virtual_nodes: usize,
+
+    /// Nodes every entry is stored on
+    #[arg(long, default_value_t = 1)]
+    replicas: usize,
+
+    /// Replicas which must store an entry for a write to succeed, a majority
+    /// of them by default
+    #[arg(long)]
+    write_quorum: Option<usize>,
+
+    /// Replicas which must answer for a read to succeed, a majority of them
+    /// by default
+    #[arg(long)]
+    read_quorum: Option<usize>,
 }
 
 #[tokio::main]
@@ -40,13 +116,43 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut nodes = vec![];
     let addr = format!("{host}:{port}", host = args.host, port = args.port);
 
-    for node in &args.nodes {
-        // For now no consideration of node weights
-        nodes.push((node.as_str(), 1 as usize));
+    for (node, weight) in &args.nodes {
+        nodes.push((node.as_str(), *weight));
+    }
+    let mut cache_network = CacheNetwork::with_servers(nodes)?;
+    let router: Box<dyn Router> = match args.routing {
+        RoutingType::Rendezvous => Box::new(Rendezvous::default()),
+        RoutingType::Ketama => Box::new(Ketama::new(args.virtual_nodes)),
+        RoutingType::Jump => Box::new(JumpHash::default()),
+        RoutingType::Maglev => Box::new(Maglev::default()),
+    };
+    cache_network.set_router(router);
+    let majority = args.replicas / 2 + 1;
+    cache_network.set_replication(
+        args.replicas,
+        args.write_quorum.unwrap_or(majority),
+        args.read_quorum.unwrap_or(majority),
+    )?;
+    match (args.server, args.http_port) {
+        (ServerType::Grpc, None) => {
+            CacheClusterServer::<RPCServer>::new(cache_network)
+                .run(&addr)
+                .await?
+        }
+        (ServerType::Grpc, Some(http_port)) => {
+            let http_addr = format!("{host}:{http_port}", host = args.host);
+            let grpc = CacheClusterServer::<RPCServer>::new(cache_network);
+            let http = grpc.share::<HTTPServer>();
+            tokio::try_join!(grpc.run(&addr), http.run(&http_addr))?;
+        }
+        (ServerType::Http, None) => {
+            CacheClusterServer::<HTTPServer>::new(cache_network)
+                .run(&addr)
+                .await?
+        }
+        (ServerType::Http, Some(_)) => {
+            return Err("--http-port is only used along with the gRPC server".into());
+        }
     }
-    let cache_network = CacheNetwork::with_servers(nodes)?;
-    CacheClusterServer::<RPCServer>::new(cache_network)
-        .run(&addr)
-        .await?;
     Ok(())
 }
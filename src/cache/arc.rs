@@ -0,0 +1,414 @@
+use crate::utils::doubly_linked_list::DoublyLinkedList;
+
+use super::{
+    expiry_after, Cache, Expiries, Expiring, IgnoreRemovals, RemovalCause, RemovalListener, Weigher,
+};
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+/// Adaptive Replacement Cache.
+///
+/// Resident keys are split between `t1` (seen once recently) and `t2` (seen
+/// at least twice). The ghost lists `b1` and `b2` remember the keys recently
+/// evicted from them, and a hit on a ghost key shifts the target size `p` of
+/// `t1` towards the list that would have kept it, so the cache keeps tuning
+/// itself between recency and frequency. All the sizes are measured in the
+/// weights of the entries.
+pub struct ArcCache<K, V> {
+    capacity: usize,
+    weigher: Box<dyn Weigher<K, V>>,
+    listener: Box<dyn RemovalListener<K, V>>,
+    expiries: Expiries<K>,
+    p: usize,
+    map: HashMap<K, CacheValue<V>>,
+    ghosts: HashMap<K, GhostValue>,
+    t1: List<K>,
+    t2: List<K>,
+    b1: List<K>,
+    b2: List<K>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Resident {
+    T1,
+    T2,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Ghost {
+    B1,
+    B2,
+}
+
+/// A list of keys along with their total weight.
+struct List<K> {
+    keys: DoublyLinkedList<K>,
+    weight: usize,
+}
+
+impl<K> List<K> {
+    fn new() -> Self {
+        List {
+            keys: DoublyLinkedList::new(),
+            weight: 0,
+        }
+    }
+
+    fn push(&mut self, key: K, weight: usize) -> usize {
+        self.weight += weight;
+        self.keys.shift_new_growing(key)
+    }
+
+    fn remove(&mut self, index: usize, weight: usize) {
+        if self.keys.remove(index).is_some() {
+            self.weight -= weight;
+        }
+    }
+}
+
+struct CacheValue<V> {
+    value: V,
+    list: Resident,
+    index: usize,
+    weight: usize,
+}
+
+struct GhostValue {
+    list: Ghost,
+    index: usize,
+    weight: usize,
+}
+
+impl<K, V> ArcCache<K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    fn resident(&mut self, list: Resident) -> &mut List<K> {
+        match list {
+            Resident::T1 => &mut self.t1,
+            Resident::T2 => &mut self.t2,
+        }
+    }
+
+    fn ghost(&mut self, list: Ghost) -> &mut List<K> {
+        match list {
+            Ghost::B1 => &mut self.b1,
+            Ghost::B2 => &mut self.b2,
+        }
+    }
+
+    fn resident_weight(&self) -> usize {
+        self.t1.weight + self.t2.weight
+    }
+
+    /// Remembers an evicted key at the head of the given ghost list.
+    fn push_ghost(&mut self, list: Ghost, key: K, weight: usize) {
+        let index = self.ghost(list).push(key.clone(), weight);
+        self.ghosts.insert(
+            key,
+            GhostValue {
+                list,
+                index,
+                weight,
+            },
+        );
+    }
+
+    /// Drops the least recently used key of the given ghost list.
+    fn drop_ghost(&mut self, list: Ghost) -> bool {
+        match self.ghost(list).keys.remove_bottom() {
+            Some(key) => {
+                if let Some(ghost) = self.ghosts.remove(&key) {
+                    self.ghost(list).weight -= ghost.weight;
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Evicts the least recently used key of either `t1` or `t2`, depending
+    /// on the target size `p`, and remembers it in the matching ghost list.
+    fn replace(&mut self, hit_in_b2: bool) -> Option<V> {
+        let t1_weight = self.t1.weight;
+        let from_t1 = (!self.t1.keys.is_empty()
+            && ((hit_in_b2 && t1_weight >= self.p) || t1_weight > self.p))
+            || self.t2.keys.is_empty();
+        let (resident, ghost) = if from_t1 {
+            (Resident::T1, Ghost::B1)
+        } else {
+            (Resident::T2, Ghost::B2)
+        };
+        let key = self.resident(resident).keys.remove_bottom()?;
+        let evacted_entry = self.map.remove(&key)?;
+        self.expiries.remove(&key);
+        self.resident(resident).weight -= evacted_entry.weight;
+        self.listener
+            .on_removal(&key, &evacted_entry.value, RemovalCause::Evicted);
+        self.push_ghost(ghost, key, evacted_entry.weight);
+        Some(evacted_entry.value)
+    }
+
+    /// Makes room for an entry of the given `weight` among the resident keys.
+    fn make_room(&mut self, weight: usize, hit_in_b2: bool) {
+        while self.resident_weight() + weight > self.capacity {
+            if self.replace(hit_in_b2).is_none() {
+                break;
+            }
+        }
+    }
+
+    /// Moves a resident key to the head of `t2`.
+    fn touch(&mut self, key: &K) {
+        let (list, index, weight) = match self.map.get(key) {
+            Some(entry) => (entry.list, entry.index, entry.weight),
+            None => return,
+        };
+        if list == Resident::T2 {
+            self.t2.keys.shift(index);
+            return;
+        }
+        self.t1.remove(index, weight);
+        let index = self.t2.push(key.clone(), weight);
+        let entry = self.map.get_mut(key).unwrap();
+        entry.list = Resident::T2;
+        entry.index = index;
+    }
+
+    fn link(&mut self, list: Resident, key: K, value: V, weight: usize) {
+        let index = self.resident(list).push(key.clone(), weight);
+        self.map.insert(
+            key,
+            CacheValue {
+                value,
+                list,
+                index,
+                weight,
+            },
+        );
+    }
+
+    fn insert(
+        &mut self,
+        key: K,
+        value: V,
+        expires_at: Option<Instant>,
+    ) -> Result<(), &'static str> {
+        let weight = self.weigher.weigh(&key, &value);
+        if weight > self.capacity {
+            return Err("entry is larger than the capacity of the cache");
+        }
+        self.expiries.set(&key, expires_at);
+
+        if let Some(entry) = self.map.get_mut(&key) {
+            let (list, old_weight) = (entry.list, entry.weight);
+            entry.value = value;
+            entry.weight = weight;
+            let list = self.resident(list);
+            list.weight = list.weight - old_weight + weight;
+            self.touch(&key);
+            // A heavier value for an existing key can overflow the capacity
+            self.make_room(0, false);
+            return Ok(());
+        }
+
+        if let Some(ghost) = self.ghosts.remove(&key) {
+            let (b1_weight, b2_weight) = (self.b1.weight.max(1), self.b2.weight.max(1));
+            match ghost.list {
+                Ghost::B1 => {
+                    let delta = (b2_weight / b1_weight).max(1) * ghost.weight;
+                    self.p = (self.p + delta).min(self.capacity);
+                }
+                Ghost::B2 => {
+                    let delta = (b1_weight / b2_weight).max(1) * ghost.weight;
+                    self.p = self.p.saturating_sub(delta);
+                }
+            }
+            self.ghost(ghost.list).remove(ghost.index, ghost.weight);
+            self.make_room(weight, ghost.list == Ghost::B2);
+            self.link(Resident::T2, key, value, weight);
+            return Ok(());
+        }
+
+        if self.t1.weight + self.b1.weight + weight > self.capacity {
+            while self.t1.weight + self.b1.weight + weight > self.capacity {
+                if !self.drop_ghost(Ghost::B1) {
+                    break;
+                }
+            }
+            // Keys evicted from an overgrown `t1` are not worth remembering
+            while self.t1.weight + weight > self.capacity {
+                match self.t1.keys.remove_bottom() {
+                    Some(lru_key) => {
+                        if let Some(evacted_entry) = self.map.remove(&lru_key) {
+                            self.expiries.remove(&lru_key);
+                            self.t1.weight -= evacted_entry.weight;
+                            self.listener.on_removal(
+                                &lru_key,
+                                &evacted_entry.value,
+                                RemovalCause::Evicted,
+                            );
+                        }
+                    }
+                    None => break,
+                }
+            }
+        } else {
+            while self.resident_weight() + self.b1.weight + self.b2.weight + weight
+                > 2 * self.capacity
+            {
+                if !self.drop_ghost(Ghost::B2) {
+                    break;
+                }
+            }
+        }
+        self.make_room(weight, false);
+        self.link(Resident::T1, key, value, weight);
+        Ok(())
+    }
+}
+
+impl<K, V> Cache<K, V> for ArcCache<K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    fn with_weigher(capacity: usize, weigher: Box<dyn Weigher<K, V>>) -> Self {
+        let capacity = if capacity > 0 { capacity } else { 10 };
+        ArcCache {
+            capacity,
+            weigher,
+            listener: Box::new(IgnoreRemovals),
+            expiries: Expiries::new(),
+            p: 0,
+            map: HashMap::new(),
+            ghosts: HashMap::new(),
+            t1: List::new(),
+            t2: List::new(),
+            b1: List::new(),
+            b2: List::new(),
+        }
+    }
+
+    fn set_removal_listener(&mut self, listener: Box<dyn RemovalListener<K, V>>) {
+        self.listener = listener;
+    }
+
+    fn evact(&mut self) -> Option<V> {
+        self.replace(false)
+    }
+
+    fn get(&mut self, key: &K) -> Option<&V> {
+        if self.remove_if_expired(key) {
+            return None;
+        }
+        self.touch(key);
+        self.map.get(key).map(|entry| &entry.value)
+    }
+
+    fn expires_at(&self, key: &K) -> Option<Instant> {
+        self.expiries.get(key)
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        self.map.contains_key(key) && !self.expiries.is_expired(key, Instant::now())
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        if let Some(ghost) = self.ghosts.remove(key) {
+            self.ghost(ghost.list).remove(ghost.index, ghost.weight);
+        }
+        let entry = self.map.remove(key)?;
+        self.expiries.remove(key);
+        self.resident(entry.list).remove(entry.index, entry.weight);
+        Some(entry.value)
+    }
+
+    fn put(&mut self, key: K, value: V) -> Result<(), &'static str> {
+        self.insert(key, value, None)
+    }
+
+    fn put_with_ttl(&mut self, key: K, value: V, ttl: Duration) -> Result<(), &'static str> {
+        self.insert(key, value, expiry_after(ttl))
+    }
+
+    fn remove_expired(&mut self, limit: usize) -> usize {
+        self.remove_expired_entries(limit)
+    }
+}
+
+impl<K, V> Expiring<K, V> for ArcCache<K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    fn expiries(&self) -> &Expiries<K> {
+        &self.expiries
+    }
+
+    fn listener(&self) -> &dyn RemovalListener<K, V> {
+        self.listener.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_keys_seen_twice() {
+        let mut cache = ArcCache::new(2);
+        cache.put(1, 10).unwrap();
+        cache.put(2, 20).unwrap();
+        assert_eq!(cache.get(&1), Some(&10));
+        cache.put(3, 30).unwrap();
+        assert!(!cache.map.contains_key(&2));
+        assert!(cache.map.contains_key(&1) && cache.map.contains_key(&3));
+        assert!(cache.ghosts.contains_key(&2));
+    }
+
+    #[test]
+    fn ghost_hits_adapt_the_target() {
+        let mut cache = ArcCache::new(2);
+        cache.put(1, 10).unwrap();
+        cache.put(2, 20).unwrap();
+        cache.get(&1);
+        cache.put(3, 30).unwrap();
+        // 2 was evicted from t1 too early, so t1 grows
+        cache.put(2, 21).unwrap();
+        assert_eq!(cache.p, 1);
+        assert!(cache.map[&2].list == Resident::T2);
+        assert!(!cache.map.contains_key(&1) && cache.ghosts.contains_key(&1));
+        // 1 was evicted from t2 too early, so t1 shrinks back
+        cache.put(1, 11).unwrap();
+        assert_eq!(cache.p, 0);
+        assert_eq!(cache.get(&1), Some(&11));
+    }
+
+    #[test]
+    fn expired_entries_are_dropped() {
+        let mut cache = ArcCache::new(3);
+        cache.put_with_ttl(1, 10, Duration::ZERO).unwrap();
+        cache.put_with_ttl(2, 20, Duration::from_secs(60)).unwrap();
+        assert_eq!(cache.remove_expired(usize::MAX), 1);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2), Some(&20));
+        assert_eq!(cache.map.len(), 1);
+    }
+
+    #[test]
+    fn weigher_bounds_the_entries() {
+        let weigher = |_: &u32, value: &String| value.len();
+        let mut cache = ArcCache::with_weigher(10, Box::new(weigher));
+        assert!(cache.put(1, "x".repeat(11)).is_err());
+        cache.put(1, "x".repeat(4)).unwrap();
+        cache.put(2, "x".repeat(4)).unwrap();
+        cache.put(3, "x".repeat(4)).unwrap();
+        assert!(!cache.map.contains_key(&1));
+        assert!(cache.resident_weight() <= 10);
+        cache.put(3, "x".repeat(9)).unwrap();
+        assert!(!cache.map.contains_key(&2));
+        assert_eq!(cache.resident_weight(), 9);
+    }
+}
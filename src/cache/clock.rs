@@ -0,0 +1,267 @@
+use super::{
+    expiry_after, Cache, Expiries, Expiring, IgnoreRemovals, Lookup, RemovalCause, RemovalListener,
+    Weigher,
+};
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+/// CLOCK (second chance) cache.
+///
+/// The keys sit in a ring swept by a hand. A read only sets the reference
+/// bit of the entry, which can be done through a shared reference, and the
+/// hand gives every referenced key a second chance by clearing its bit
+/// before evicting the first key found unreferenced.
+pub struct ClockCache<K, V> {
+    capacity: usize,
+    used: usize,
+    weigher: Box<dyn Weigher<K, V>>,
+    listener: Box<dyn RemovalListener<K, V>>,
+    expiries: Expiries<K>,
+    map: HashMap<K, CacheValue<V>>,
+    ring: Vec<Option<K>>,
+    free_slots: Vec<usize>,
+    hand: usize,
+}
+
+struct CacheValue<V> {
+    value: V,
+    slot: usize,
+    weight: usize,
+    referenced: AtomicBool,
+}
+
+impl<K, V> ClockCache<K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    fn insert(
+        &mut self,
+        key: K,
+        value: V,
+        expires_at: Option<Instant>,
+    ) -> Result<(), &'static str> {
+        let weight = self.weigher.weigh(&key, &value);
+        if weight > self.capacity {
+            return Err("entry is larger than the capacity of the cache");
+        }
+        self.expiries.set(&key, expires_at);
+
+        if let Some(entry) = self.map.get_mut(&key) {
+            self.used = self.used - entry.weight + weight;
+            entry.value = value;
+            entry.weight = weight;
+            entry.referenced.store(true, Ordering::Relaxed);
+            // A heavier value for an existing key can overflow the capacity
+            while self.used > self.capacity {
+                if self.evact().is_none() {
+                    break;
+                }
+            }
+            return Ok(());
+        }
+
+        while self.used + weight > self.capacity {
+            if self.evact().is_none() {
+                break;
+            }
+        }
+        let slot = match self.free_slots.pop() {
+            Some(slot) => {
+                self.ring[slot] = Some(key.clone());
+                slot
+            }
+            None => {
+                self.ring.push(Some(key.clone()));
+                self.ring.len() - 1
+            }
+        };
+        self.used += weight;
+        self.map.insert(
+            key,
+            CacheValue {
+                value,
+                slot,
+                weight,
+                referenced: AtomicBool::new(false),
+            },
+        );
+        Ok(())
+    }
+}
+
+impl<K, V> Cache<K, V> for ClockCache<K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    fn with_weigher(capacity: usize, weigher: Box<dyn Weigher<K, V>>) -> Self {
+        let capacity = if capacity > 0 { capacity } else { 10 };
+        ClockCache {
+            capacity,
+            used: 0,
+            weigher,
+            listener: Box::new(IgnoreRemovals),
+            expiries: Expiries::new(),
+            map: HashMap::new(),
+            ring: Vec::new(),
+            free_slots: Vec::new(),
+            hand: 0,
+        }
+    }
+
+    fn set_removal_listener(&mut self, listener: Box<dyn RemovalListener<K, V>>) {
+        self.listener = listener;
+    }
+
+    fn evact(&mut self) -> Option<V> {
+        if self.map.is_empty() {
+            return None;
+        }
+        loop {
+            if self.hand >= self.ring.len() {
+                self.hand = 0;
+            }
+            let slot = self.hand;
+            self.hand += 1;
+            let key = match &self.ring[slot] {
+                Some(key) => key,
+                None => continue,
+            };
+            let entry = self.map.get(key)?;
+            if !entry.referenced.swap(false, Ordering::Relaxed) {
+                let key = key.clone();
+                let value = self.remove(&key)?;
+                self.listener
+                    .on_removal(&key, &value, RemovalCause::Evicted);
+                return Some(value);
+            }
+        }
+    }
+
+    fn get(&mut self, key: &K) -> Option<&V> {
+        if self.remove_if_expired(key) {
+            return None;
+        }
+        let entry = self.map.get(key)?;
+        entry.referenced.store(true, Ordering::Relaxed);
+        Some(&entry.value)
+    }
+
+    fn get_shared(&self, key: &K) -> Lookup<'_, V> {
+        match self.map.get(key) {
+            // Expired entries are removed through the exclusive path
+            Some(_) if self.expiries.is_expired(key, Instant::now()) => Lookup::Exclusive,
+            Some(entry) => {
+                entry.referenced.store(true, Ordering::Relaxed);
+                Lookup::Hit(&entry.value)
+            }
+            None => Lookup::Miss,
+        }
+    }
+
+    fn expires_at(&self, key: &K) -> Option<Instant> {
+        self.expiries.get(key)
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        self.map.contains_key(key) && !self.expiries.is_expired(key, Instant::now())
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        let entry = self.map.remove(key)?;
+        self.expiries.remove(key);
+        self.ring[entry.slot] = None;
+        self.free_slots.push(entry.slot);
+        self.used -= entry.weight;
+        Some(entry.value)
+    }
+
+    fn put(&mut self, key: K, value: V) -> Result<(), &'static str> {
+        self.insert(key, value, None)
+    }
+
+    fn put_with_ttl(&mut self, key: K, value: V, ttl: Duration) -> Result<(), &'static str> {
+        self.insert(key, value, expiry_after(ttl))
+    }
+
+    fn remove_expired(&mut self, limit: usize) -> usize {
+        self.remove_expired_entries(limit)
+    }
+}
+
+impl<K, V> Expiring<K, V> for ClockCache<K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    fn expiries(&self) -> &Expiries<K> {
+        &self.expiries
+    }
+
+    fn listener(&self) -> &dyn RemovalListener<K, V> {
+        self.listener.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn referenced_keys_get_a_second_chance() {
+        let mut cache = ClockCache::new(3);
+        for key in 1..=3 {
+            cache.put(key, key * 10).unwrap();
+        }
+        cache.get(&1);
+        cache.put(4, 40).unwrap();
+        assert!(!cache.map.contains_key(&2));
+        // The freed slot is reused
+        assert_eq!(cache.ring.len(), 3);
+        cache.put(5, 50).unwrap();
+        assert!(!cache.map.contains_key(&3));
+        // The second chance of 1 is used up
+        cache.put(6, 60).unwrap();
+        assert!(!cache.map.contains_key(&1));
+        assert!(cache.map.contains_key(&4) && cache.map.contains_key(&5));
+    }
+
+    #[test]
+    fn shared_reads_set_the_reference_bit() {
+        let mut cache = ClockCache::new(2);
+        cache.put(1, 10).unwrap();
+        cache.put(2, 20).unwrap();
+        cache.put_with_ttl(3, 30, Duration::ZERO).unwrap();
+        assert!(matches!(cache.get_shared(&2), Lookup::Hit(&20)));
+        assert!(matches!(cache.get_shared(&9), Lookup::Miss));
+        assert!(matches!(cache.get_shared(&3), Lookup::Exclusive));
+        assert!(cache.map[&2].referenced.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn expired_entries_are_dropped() {
+        let mut cache = ClockCache::new(3);
+        cache.put_with_ttl(1, 10, Duration::ZERO).unwrap();
+        cache.put_with_ttl(2, 20, Duration::from_secs(60)).unwrap();
+        assert_eq!(cache.remove_expired(usize::MAX), 1);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2), Some(&20));
+        assert_eq!(cache.used, 1);
+    }
+
+    #[test]
+    fn weigher_bounds_the_entries() {
+        let weigher = |_: &u32, value: &String| value.len();
+        let mut cache = ClockCache::with_weigher(10, Box::new(weigher));
+        assert!(cache.put(1, "x".repeat(11)).is_err());
+        cache.put(1, "x".repeat(4)).unwrap();
+        cache.put(2, "x".repeat(4)).unwrap();
+        cache.put(3, "x".repeat(4)).unwrap();
+        assert!(!cache.map.contains_key(&1));
+        cache.put(3, "x".repeat(9)).unwrap();
+        assert!(!cache.map.contains_key(&2));
+        assert_eq!(cache.used, 9);
+    }
+}
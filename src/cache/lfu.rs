@@ -0,0 +1,334 @@
+use crate::utils::doubly_linked_list::DoublyLinkedList;
+
+use super::{
+    expiry_after, Cache, Expiries, Expiring, IgnoreRemovals, RemovalCause, RemovalListener, Weigher,
+};
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+/// Initial number of slots of a newly created frequency bucket.
+const BUCKET_CAPACITY: usize = 4;
+
+/// Least Frequently Used cache.
+///
+/// Keys with the same access frequency are kept in a bucket ordered by
+/// recency, so all the operations run in O(1) and ties are broken in LRU
+/// order.
+pub struct LFUCache<K, V> {
+    capacity: usize,
+    used: usize,
+    weigher: Box<dyn Weigher<K, V>>,
+    listener: Box<dyn RemovalListener<K, V>>,
+    expiries: Expiries<K>,
+    map: HashMap<K, CacheValue<V>>,
+    buckets: HashMap<usize, Bucket<K>>,
+    /// Lowest frequency with a bucket, which heads the list of the buckets
+    min_frequency: Option<usize>,
+}
+
+/// Keys with the same access frequency by recency, linked to the buckets of
+/// the nearest lower and higher frequencies so that the lowest one is always
+/// known without scanning.
+struct Bucket<K> {
+    keys: DoublyLinkedList<K>,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+struct CacheValue<V> {
+    value: V,
+    frequency: usize,
+    index: usize,
+    weight: usize,
+}
+
+impl<K, V> LFUCache<K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    /// Puts the key at the head of the bucket of the given `frequency` and
+    /// returns its index in that bucket. A missing bucket is created and
+    /// linked right after the bucket of the frequency `after`, or as the
+    /// first bucket if `None`.
+    fn push_to_bucket(&mut self, frequency: usize, after: Option<usize>, key: K) -> usize {
+        if !self.buckets.contains_key(&frequency) {
+            let next = match after {
+                Some(after) => self
+                    .buckets
+                    .get_mut(&after)
+                    .unwrap()
+                    .next
+                    .replace(frequency),
+                None => self.min_frequency.replace(frequency),
+            };
+            if let Some(next) = next {
+                self.buckets.get_mut(&next).unwrap().prev = Some(frequency);
+            }
+            self.buckets.insert(
+                frequency,
+                Bucket {
+                    keys: DoublyLinkedList::with_capacity(BUCKET_CAPACITY),
+                    prev: after,
+                    next,
+                },
+            );
+        }
+        self.buckets
+            .get_mut(&frequency)
+            .unwrap()
+            .keys
+            .shift_new_growing(key)
+    }
+
+    /// Drops the bucket of the given `frequency` once it becomes empty,
+    /// linking its neighbours to each other.
+    fn drop_if_empty(&mut self, frequency: usize) {
+        if !self
+            .buckets
+            .get(&frequency)
+            .is_some_and(|bucket| bucket.keys.is_empty())
+        {
+            return;
+        }
+        let Bucket { prev, next, .. } = self.buckets.remove(&frequency).unwrap();
+        match prev {
+            Some(prev) => self.buckets.get_mut(&prev).unwrap().next = next,
+            None => self.min_frequency = next,
+        }
+        if let Some(next) = next {
+            self.buckets.get_mut(&next).unwrap().prev = prev;
+        }
+    }
+
+    /// Removes the key at `index` from the bucket of the given `frequency`,
+    /// dropping the bucket once it becomes empty.
+    fn remove_from_bucket(&mut self, frequency: usize, index: usize) {
+        if let Some(bucket) = self.buckets.get_mut(&frequency) {
+            bucket.keys.remove(index);
+            self.drop_if_empty(frequency);
+        }
+    }
+
+    /// Moves the key to the bucket of the next frequency.
+    fn touch(&mut self, key: &K) {
+        let (frequency, index) = match self.map.get(key) {
+            Some(entry) => (entry.frequency, entry.index),
+            None => return,
+        };
+        // The next bucket is linked while the current one is still there
+        let next_index = self.push_to_bucket(frequency + 1, Some(frequency), key.clone());
+        self.remove_from_bucket(frequency, index);
+        let index = next_index;
+        let entry = self.map.get_mut(key).unwrap();
+        entry.frequency = frequency + 1;
+        entry.index = index;
+    }
+
+    fn insert(
+        &mut self,
+        key: K,
+        value: V,
+        expires_at: Option<Instant>,
+    ) -> Result<(), &'static str> {
+        let weight = self.weigher.weigh(&key, &value);
+        if weight > self.capacity {
+            return Err("entry is larger than the capacity of the cache");
+        }
+        self.expiries.set(&key, expires_at);
+
+        if let Some(entry) = self.map.get_mut(&key) {
+            self.used = self.used - entry.weight + weight;
+            entry.value = value;
+            entry.weight = weight;
+            self.touch(&key);
+            // A heavier value for an existing key can overflow the capacity
+            while self.used > self.capacity {
+                if self.evact().is_none() {
+                    break;
+                }
+            }
+            return Ok(());
+        }
+
+        while self.used + weight > self.capacity {
+            if self.evact().is_none() {
+                break;
+            }
+        }
+        let index = self.push_to_bucket(1, None, key.clone());
+        self.used += weight;
+        self.map.insert(
+            key,
+            CacheValue {
+                value,
+                frequency: 1,
+                index,
+                weight,
+            },
+        );
+        Ok(())
+    }
+}
+
+impl<K, V> Cache<K, V> for LFUCache<K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    fn with_weigher(capacity: usize, weigher: Box<dyn Weigher<K, V>>) -> Self {
+        let capacity = if capacity > 0 { capacity } else { 10 };
+        LFUCache {
+            map: HashMap::new(),
+            capacity,
+            used: 0,
+            weigher,
+            listener: Box::new(IgnoreRemovals),
+            expiries: Expiries::new(),
+            buckets: HashMap::new(),
+            min_frequency: None,
+        }
+    }
+
+    fn set_removal_listener(&mut self, listener: Box<dyn RemovalListener<K, V>>) {
+        self.listener = listener;
+    }
+
+    fn evact(&mut self) -> Option<V> {
+        let min_frequency = self.min_frequency?;
+        let lfu_key = self.buckets.get_mut(&min_frequency)?.keys.remove_bottom();
+        self.drop_if_empty(min_frequency);
+        if let Some(lfu_key) = lfu_key {
+            if let Some(evacted_entry) = self.map.remove(&lfu_key) {
+                self.expiries.remove(&lfu_key);
+                self.used -= evacted_entry.weight;
+                self.listener
+                    .on_removal(&lfu_key, &evacted_entry.value, RemovalCause::Evicted);
+                return Some(evacted_entry.value);
+            }
+        }
+        None
+    }
+
+    fn get(&mut self, key: &K) -> Option<&V> {
+        if self.remove_if_expired(key) {
+            return None;
+        }
+        self.touch(key);
+        self.map.get(key).map(|entry| &entry.value)
+    }
+
+    fn expires_at(&self, key: &K) -> Option<Instant> {
+        self.expiries.get(key)
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        self.map.contains_key(key) && !self.expiries.is_expired(key, Instant::now())
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        let entry = self.map.remove(key)?;
+        self.expiries.remove(key);
+        self.remove_from_bucket(entry.frequency, entry.index);
+        self.used -= entry.weight;
+        Some(entry.value)
+    }
+
+    fn put(&mut self, key: K, value: V) -> Result<(), &'static str> {
+        self.insert(key, value, None)
+    }
+
+    fn put_with_ttl(&mut self, key: K, value: V, ttl: Duration) -> Result<(), &'static str> {
+        self.insert(key, value, expiry_after(ttl))
+    }
+
+    fn remove_expired(&mut self, limit: usize) -> usize {
+        self.remove_expired_entries(limit)
+    }
+}
+
+impl<K, V> Expiring<K, V> for LFUCache<K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    fn expiries(&self) -> &Expiries<K> {
+        &self.expiries
+    }
+
+    fn listener(&self) -> &dyn RemovalListener<K, V> {
+        self.listener.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_the_least_frequently_used() {
+        let mut cache = LFUCache::new(3);
+        for key in 1..=3 {
+            cache.put(key, key * 10).unwrap();
+        }
+        cache.get(&1);
+        cache.get(&1);
+        cache.get(&2);
+        cache.put(4, 40).unwrap();
+        assert!(!cache.map.contains_key(&3));
+        // Ties are broken by recency
+        cache.get(&4);
+        assert_eq!(cache.evact(), Some(20));
+        assert_eq!(cache.evact(), Some(40));
+        assert_eq!(cache.evact(), Some(10));
+        assert_eq!(cache.evact(), None);
+    }
+
+    #[test]
+    fn buckets_are_relinked() {
+        let mut cache = LFUCache::new(4);
+        for key in 1..=4 {
+            cache.put(key, key).unwrap();
+        }
+        // Frequencies 1, 2, 3 and 5, then the middle buckets empty out
+        for (key, reads) in [(2, 1), (3, 2), (4, 4)] {
+            for _ in 0..reads {
+                cache.get(&key);
+            }
+        }
+        cache.remove(&2);
+        cache.remove(&1);
+        assert_eq!(cache.min_frequency, Some(3));
+        assert_eq!(cache.evact(), Some(3));
+        assert_eq!(cache.evact(), Some(4));
+        assert!(cache.buckets.is_empty());
+        assert_eq!(cache.min_frequency, None);
+    }
+
+    #[test]
+    fn expired_entries_are_dropped() {
+        let mut cache = LFUCache::new(3);
+        cache.put_with_ttl(1, 10, Duration::ZERO).unwrap();
+        cache.put_with_ttl(2, 20, Duration::from_secs(60)).unwrap();
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.remove_expired(usize::MAX), 0);
+        assert_eq!(cache.get(&2), Some(&20));
+        assert_eq!(cache.map.len(), 1);
+    }
+
+    #[test]
+    fn weigher_bounds_the_entries() {
+        let weigher = |_: &u32, value: &String| value.len();
+        let mut cache = LFUCache::with_weigher(10, Box::new(weigher));
+        assert!(cache.put(1, "x".repeat(11)).is_err());
+        cache.put(1, "x".repeat(4)).unwrap();
+        cache.put(2, "x".repeat(4)).unwrap();
+        cache.get(&1);
+        cache.put(3, "x".repeat(4)).unwrap();
+        assert!(cache.map.contains_key(&1) && !cache.map.contains_key(&2));
+        // Growing an entry makes room for it
+        cache.put(3, "x".repeat(8)).unwrap();
+        assert!(!cache.map.contains_key(&1));
+        assert_eq!(cache.used, 8);
+    }
+}
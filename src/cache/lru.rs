@@ -1,36 +1,96 @@
 use crate::utils::doubly_linked_list::DoublyLinkedList;
 
-use super::Cache;
-use std::{collections::HashMap, hash::Hash};
+use super::{
+    expiry_after, Cache, Expiries, Expiring, IgnoreRemovals, RemovalCause, RemovalListener, Weigher,
+};
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    time::{Duration, Instant},
+};
 
+/// Least Recently Used cache.
 pub struct LRUCache<K, V> {
     capacity: usize,
+    used: usize,
+    weigher: Box<dyn Weigher<K, V>>,
+    listener: Box<dyn RemovalListener<K, V>>,
     map: HashMap<K, CacheValue<V>>,
     lru_order: DoublyLinkedList<K>,
+    expiries: Expiries<K>,
 }
 
 struct CacheValue<V> {
     value: V,
     index: usize,
+    weight: usize,
+}
+
+impl<K, V> LRUCache<K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    fn insert(
+        &mut self,
+        key: K,
+        value: V,
+        expires_at: Option<Instant>,
+    ) -> Result<(), &'static str> {
+        let weight = self.weigher.weigh(&key, &value);
+        if weight > self.capacity {
+            return Err("entry is larger than the capacity of the cache");
+        }
+
+        self.remove(&key);
+        while self.used + weight > self.capacity {
+            if self.evact().is_none() {
+                break;
+            }
+        }
+        let index = self.lru_order.shift_new_growing(key.clone());
+        self.used += weight;
+        self.expiries.set(&key, expires_at);
+        self.map.insert(
+            key,
+            CacheValue {
+                value,
+                index,
+                weight,
+            },
+        );
+        Ok(())
+    }
 }
 
 impl<K, V> Cache<K, V> for LRUCache<K, V>
 where
     K: Eq + Hash + Clone,
 {
-    fn new(capacity: usize) -> Self {
+    fn with_weigher(capacity: usize, weigher: Box<dyn Weigher<K, V>>) -> Self {
         let capacity = if capacity > 0 { capacity } else { 10 };
         LRUCache {
-            map: HashMap::with_capacity(capacity),
+            map: HashMap::new(),
             capacity,
-            lru_order: DoublyLinkedList::with_capacity(capacity),
+            used: 0,
+            weigher,
+            listener: Box::new(IgnoreRemovals),
+            lru_order: DoublyLinkedList::new(),
+            expiries: Expiries::new(),
         }
     }
 
+    fn set_removal_listener(&mut self, listener: Box<dyn RemovalListener<K, V>>) {
+        self.listener = listener;
+    }
+
     fn evact(&mut self) -> Option<V> {
         let lru_key = self.lru_order.remove_bottom();
         if let Some(lru_key) = lru_key {
             if let Some(evacted_entry) = self.map.remove(&lru_key) {
+                self.used -= evacted_entry.weight;
+                self.expiries.remove(&lru_key);
+                self.listener
+                    .on_removal(&lru_key, &evacted_entry.value, RemovalCause::Evicted);
                 return Some(evacted_entry.value);
             }
         }
@@ -38,6 +98,9 @@ where
     }
 
     fn get(&mut self, key: &K) -> Option<&V> {
+        if self.remove_if_expired(key) {
+            return None;
+        }
         let entry = self.map.get(key);
         if let Some(entry) = entry {
             let index = entry.index;
@@ -47,16 +110,169 @@ where
         None
     }
 
+    fn expires_at(&self, key: &K) -> Option<Instant> {
+        self.expiries.get(key)
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        self.map.contains_key(key) && !self.expiries.is_expired(key, Instant::now())
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        let entry = self.map.remove(key)?;
+        self.expiries.remove(key);
+        self.lru_order.remove(entry.index);
+        self.used -= entry.weight;
+        Some(entry.value)
+    }
+
     fn put(&mut self, key: K, value: V) -> Result<(), &'static str> {
-        if self.map.len() == self.capacity {
-            self.evact();
-        }
-        match self.lru_order.shift_new(key.clone()) {
-            Ok(index) => {
-                self.map.insert(key, CacheValue { value, index });
-                Ok(())
-            }
-            Err(err) => Err(err),
+        self.insert(key, value, None)
+    }
+
+    fn put_with_ttl(&mut self, key: K, value: V, ttl: Duration) -> Result<(), &'static str> {
+        self.insert(key, value, expiry_after(ttl))
+    }
+
+    fn remove_expired(&mut self, limit: usize) -> usize {
+        self.remove_expired_entries(limit)
+    }
+}
+
+impl<K, V> Expiring<K, V> for LRUCache<K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    fn expiries(&self) -> &Expiries<K> {
+        &self.expiries
+    }
+
+    fn listener(&self) -> &dyn RemovalListener<K, V> {
+        self.listener.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn evicts_the_least_recently_used() {
+        let mut cache = LRUCache::new(3);
+        for key in 1..=3 {
+            cache.put(key, key * 10).unwrap();
         }
+        assert_eq!(cache.get(&1), Some(&10));
+        cache.put(4, 40).unwrap();
+        assert!(!cache.map.contains_key(&2));
+        assert_eq!(cache.evact(), Some(30));
+        assert_eq!(cache.evact(), Some(10));
+        assert_eq!(cache.evact(), Some(40));
+        assert_eq!(cache.evact(), None);
+    }
+
+    #[test]
+    fn overwriting_refreshes_the_entry() {
+        let mut cache = LRUCache::new(2);
+        cache.put(1, 10).unwrap();
+        cache.put(2, 20).unwrap();
+        cache.put(1, 11).unwrap();
+        cache.put(3, 30).unwrap();
+        assert_eq!(cache.get(&1), Some(&11));
+        assert!(!cache.map.contains_key(&2));
+    }
+
+    #[test]
+    fn expired_entries_are_dropped() {
+        let mut cache = LRUCache::new(3);
+        cache.put_with_ttl(1, 10, Duration::ZERO).unwrap();
+        cache.put_with_ttl(2, 20, Duration::ZERO).unwrap();
+        cache.put_with_ttl(3, 30, Duration::from_secs(60)).unwrap();
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.remove_expired(usize::MAX), 1);
+        assert_eq!(cache.get(&3), Some(&30));
+        assert_eq!(cache.map.len(), 1);
+    }
+
+    #[test]
+    fn expired_entries_are_removed_in_batches() {
+        let mut cache = LRUCache::new(4);
+        cache.put_with_ttl(1, 10, Duration::ZERO).unwrap();
+        cache.put_with_ttl(2, 20, Duration::ZERO).unwrap();
+        cache.put_with_ttl(3, 30, Duration::from_secs(60)).unwrap();
+        cache.put(4, 40).unwrap();
+        assert_eq!(cache.remove_expired(1), 1);
+        assert!(!cache.map.contains_key(&1) && cache.map.contains_key(&2));
+        assert_eq!(cache.remove_expired(1), 1);
+        assert_eq!(cache.remove_expired(1), 0);
+        assert_eq!(cache.map.len(), 2);
+    }
+
+    #[test]
+    fn weigher_bounds_the_entries() {
+        let weigher = |_: &u32, value: &String| value.len();
+        let mut cache = LRUCache::with_weigher(10, Box::new(weigher));
+        assert!(cache.put(1, "x".repeat(11)).is_err());
+        cache.put(1, "x".repeat(4)).unwrap();
+        cache.put(2, "x".repeat(4)).unwrap();
+        cache.put(3, "x".repeat(4)).unwrap();
+        assert!(!cache.map.contains_key(&1));
+        assert!(cache.map.contains_key(&2) && cache.map.contains_key(&3));
+        cache.put(4, "x".repeat(10)).unwrap();
+        assert!(!cache.map.contains_key(&2) && !cache.map.contains_key(&3));
+    }
+
+    #[test]
+    fn expiries_are_reported() {
+        let removed = Arc::new(Mutex::new(vec![]));
+        let mut cache = LRUCache::new(3);
+        let listener = Arc::clone(&removed);
+        cache.set_removal_listener(Box::new(move |key: &u32, _: &u32, cause| {
+            listener.lock().unwrap().push((*key, cause));
+        }));
+        cache.put_with_ttl(1, 10, Duration::ZERO).unwrap();
+        cache.put_with_ttl(2, 20, Duration::ZERO).unwrap();
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.remove_expired(usize::MAX), 1);
+        assert_eq!(
+            *removed.lock().unwrap(),
+            [(1, RemovalCause::Expired), (2, RemovalCause::Expired)]
+        );
+    }
+
+    #[test]
+    fn expiries_too_far_away_never_come() {
+        let mut cache = LRUCache::new(3);
+        cache.put_with_ttl(1, 10, Duration::from_secs(60)).unwrap();
+        assert!(cache.expires_at(&1).is_some());
+        cache.put_with_ttl(2, 20, Duration::MAX).unwrap();
+        assert_eq!(cache.expires_at(&2), None);
+        assert_eq!(cache.get(&2), Some(&20));
+    }
+
+    #[test]
+    fn expiries_are_forgotten_with_their_entries() {
+        let mut cache = LRUCache::new(2);
+        cache.put_with_ttl(1, 10, Duration::from_secs(60)).unwrap();
+        cache.put_with_ttl(2, 20, Duration::from_secs(60)).unwrap();
+        cache.put_with_ttl(3, 30, Duration::from_secs(60)).unwrap();
+        cache.put(2, 21).unwrap();
+        assert_eq!(cache.expires_at(&2), None);
+        cache.remove(&3);
+        assert!(cache.expiries.expires_at.is_empty());
+    }
+
+    #[test]
+    fn contains_key_is_not_an_access() {
+        let mut cache = LRUCache::new(2);
+        cache.put(1, 10).unwrap();
+        cache.put(2, 20).unwrap();
+        cache.put_with_ttl(3, 30, Duration::ZERO).unwrap();
+        assert!(!cache.contains_key(&3));
+        assert!(cache.contains_key(&2));
+        cache.put(4, 40).unwrap();
+        assert!(!cache.contains_key(&2));
+        assert!(cache.contains_key(&4));
     }
 }
@@ -1,20 +1,304 @@
-use std::hash::Hash;
+use bytes::Bytes;
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+    mem::size_of,
+    time::{Duration, Instant},
+};
+pub mod arc;
+pub mod clock;
+pub mod lfu;
 pub mod lru;
+pub mod s3_fifo;
+pub mod sharded;
+pub mod tiny_lfu;
 
-pub trait Cache<K, V>
+/// Reports the number of heap bytes owned by a value, used to account the
+/// memory taken by the cached entries.
+pub trait MemorySize {
+    fn memory_size(&self) -> usize;
+}
+
+impl MemorySize for String {
+    fn memory_size(&self) -> usize {
+        self.len()
+    }
+}
+
+impl MemorySize for Vec<u8> {
+    fn memory_size(&self) -> usize {
+        self.len()
+    }
+}
+
+impl MemorySize for Bytes {
+    fn memory_size(&self) -> usize {
+        self.len()
+    }
+}
+
+/// A value stored along with the version it was written at, which lets the
+/// writers detect whether the value changed since they read it.
+#[derive(Clone, Debug)]
+pub struct Versioned<V> {
+    pub value: V,
+    pub version: u64,
+    /// Opaque flags memcached clients store along with the value, `0` for
+    /// the values written over the other protocols
+    pub flags: u32,
+}
+
+impl<V: MemorySize> MemorySize for Versioned<V> {
+    fn memory_size(&self) -> usize {
+        self.value.memory_size()
+    }
+}
+
+/// Bytes of bookkeeping a cache roughly needs per entry besides the inline
+/// key and value, e.g. the list links and the policy specific fields.
+const ENTRY_OVERHEAD: usize = 48;
+
+/// Computes the cost of an entry, which the entries of a cache must fit in
+/// its capacity by.
+pub trait Weigher<K, V>: Send + Sync {
+    fn weigh(&self, key: &K, value: &V) -> usize;
+}
+
+impl<K, V, F> Weigher<K, V> for F
+where
+    F: Fn(&K, &V) -> usize + Send + Sync,
+{
+    fn weigh(&self, key: &K, value: &V) -> usize {
+        self(key, value)
+    }
+}
+
+/// Weighs every entry as `1`, so the capacity is the number of entries.
+pub struct EntryWeigher;
+
+impl<K, V> Weigher<K, V> for EntryWeigher {
+    fn weigh(&self, _: &K, _: &V) -> usize {
+        1
+    }
+}
+
+/// Weighs an entry by the bytes it takes, so the capacity is a memory budget.
+pub struct MemoryWeigher;
+
+impl<K, V> Weigher<K, V> for MemoryWeigher
+where
+    K: MemorySize,
+    V: MemorySize,
+{
+    fn weigh(&self, key: &K, value: &V) -> usize {
+        // The key is stored both in the map and in the eviction order list
+        let inline = 2 * size_of::<K>() + size_of::<V>() + ENTRY_OVERHEAD;
+        inline + key.memory_size() + value.memory_size()
+    }
+}
+
+/// Why a cache dropped an entry on its own.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RemovalCause {
+    /// The entry was evicted to make room for other entries
+    Evicted,
+    /// The time to live of the entry ran out
+    Expired,
+}
+
+/// Gets notified of the entries a cache drops on its own, i.e. not through
+/// [`Cache::remove`] or by overwriting them.
+pub trait RemovalListener<K, V>: Send + Sync {
+    fn on_removal(&self, key: &K, value: &V, cause: RemovalCause);
+}
+
+impl<K, V, F> RemovalListener<K, V> for F
+where
+    F: Fn(&K, &V, RemovalCause) + Send + Sync,
+{
+    fn on_removal(&self, key: &K, value: &V, cause: RemovalCause) {
+        self(key, value, cause)
+    }
+}
+
+/// Ignores every removal, which is what a cache does until it is given a
+/// listener.
+pub struct IgnoreRemovals;
+
+impl<K, V> RemovalListener<K, V> for IgnoreRemovals {
+    fn on_removal(&self, _: &K, _: &V, _: RemovalCause) {}
+}
+
+/// Returns when an entry put now with the given time to live expires, or
+/// `None` if that is too far away to be represented.
+pub(crate) fn expiry_after(ttl: Duration) -> Option<Instant> {
+    Instant::now().checked_add(ttl)
+}
+
+/// When the entries of a cache expire, kept apart from the entries so that
+/// every policy checks and removes the expired entries the same way. Only
+/// the entries stored with a time to live are tracked.
+pub(crate) struct Expiries<K> {
+    expires_at: HashMap<K, Instant>,
+    /// The keys by the time they expire at, so that the expired entries are
+    /// found without going through all of them
+    order: BTreeMap<Instant, Vec<K>>,
+}
+
+impl<K> Expiries<K>
+where
+    K: Eq + Hash + Clone,
+{
+    pub(crate) fn new() -> Self {
+        Expiries {
+            expires_at: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    /// Sets when the entry stored against the key expires, where `None`
+    /// means never.
+    pub(crate) fn set(&mut self, key: &K, expires_at: Option<Instant>) {
+        self.remove(key);
+        if let Some(expires_at) = expires_at {
+            self.expires_at.insert(key.clone(), expires_at);
+            self.order.entry(expires_at).or_default().push(key.clone());
+        }
+    }
+
+    /// Forgets the expiry of an entry removed from the cache.
+    pub(crate) fn remove(&mut self, key: &K) {
+        let expires_at = match self.expires_at.remove(key) {
+            Some(expires_at) => expires_at,
+            None => return,
+        };
+        if let Some(keys) = self.order.get_mut(&expires_at) {
+            keys.retain(|other| other != key);
+            if keys.is_empty() {
+                self.order.remove(&expires_at);
+            }
+        }
+    }
+
+    /// Returns when the entry stored against the key expires, if ever.
+    pub(crate) fn get(&self, key: &K) -> Option<Instant> {
+        self.expires_at.get(key).copied()
+    }
+
+    /// Returns whether the entry stored against the key expired by `now`.
+    pub(crate) fn is_expired(&self, key: &K, now: Instant) -> bool {
+        matches!(self.get(key), Some(expires_at) if expires_at <= now)
+    }
+
+    /// Returns the keys of up to `limit` of the entries which expired by
+    /// `now`, the longest expired first.
+    fn expired(&self, now: Instant, limit: usize) -> Vec<K> {
+        self.order
+            .range(..=now)
+            .flat_map(|(_, keys)| keys)
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
+
+/// A policy keeping the expiry of its entries in [`Expiries`], which lets
+/// the policies share how they drop the expired entries.
+pub(crate) trait Expiring<K, V>: Cache<K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    fn expiries(&self) -> &Expiries<K>;
+
+    fn listener(&self) -> &dyn RemovalListener<K, V>;
+
+    /// Removes the entry stored against the key if it expired, notifying
+    /// the listener, and returns whether it did.
+    fn remove_if_expired(&mut self, key: &K) -> bool {
+        if !self.expiries().is_expired(key, Instant::now()) {
+            return false;
+        }
+        if let Some(value) = self.remove(key) {
+            self.listener()
+                .on_removal(key, &value, RemovalCause::Expired);
+        }
+        true
+    }
+
+    /// Removes up to `limit` of the expired entries, notifying the listener
+    /// of each, and returns how many were removed.
+    fn remove_expired_entries(&mut self, limit: usize) -> usize {
+        let expired = self.expiries().expired(Instant::now(), limit);
+        for key in &expired {
+            if let Some(value) = self.remove(key) {
+                self.listener()
+                    .on_removal(key, &value, RemovalCause::Expired);
+            }
+        }
+        expired.len()
+    }
+}
+
+/// Outcome of looking up a key through a shared reference to the cache.
+pub enum Lookup<'a, V> {
+    Hit(&'a V),
+    Miss,
+    /// The policy needs exclusive access to serve this read
+    Exclusive,
+}
+
+pub trait Cache<K, V>: Sized
 where
     K: Eq + Hash,
 {
-    /// Instantiates a new Cache
-    fn new(capacity: usize) -> Self;
+    /// Instantiates a new Cache that holds at most `capacity` entries
+    fn new(capacity: usize) -> Self {
+        Self::with_weigher(capacity, Box::new(EntryWeigher))
+    }
+
+    /// Instantiates a new Cache where the weights of all the entries, as
+    /// computed by the `weigher`, add up to at most `capacity`
+    fn with_weigher(capacity: usize, weigher: Box<dyn Weigher<K, V>>) -> Self;
+
+    /// Sets the listener notified of the entries evicted or expired by the
+    /// cache
+    fn set_removal_listener(&mut self, listener: Box<dyn RemovalListener<K, V>>);
 
     /// Puts a new key-value pair into the cache
     fn put(&mut self, key: K, value: V) -> Result<(), &'static str>;
 
+    /// Puts a new key-value pair into the cache that expires after the given `ttl`,
+    /// or never if the `ttl` is too long for the expiry to be represented
+    fn put_with_ttl(&mut self, key: K, value: V, ttl: Duration) -> Result<(), &'static str>;
+
     /// Returns the stored value against the given key
     fn get(&mut self, key: &K) -> Option<&V>;
 
+    /// Returns the stored value against the given key without exclusive
+    /// access, so that concurrent reads can share a lock. Policies which
+    /// reorder their entries on every read keep the default, which defers
+    /// every read to [`Cache::get`].
+    fn get_shared(&self, _key: &K) -> Lookup<'_, V> {
+        Lookup::Exclusive
+    }
+
+    /// Returns when the entry stored against the given key expires, if it
+    /// is stored with a time to live
+    fn expires_at(&self, key: &K) -> Option<Instant>;
+
+    /// Returns whether an entry that has not expired is stored against the
+    /// given key, without counting as an access to it
+    fn contains_key(&self, key: &K) -> bool;
+
+    /// Removes the key-value pair stored against the given key and returns
+    /// the removed value
+    fn remove(&mut self, key: &K) -> Option<V>;
+
     /// When the Cache capacity is filled, this function removes key-value pair
     /// based on different policies.
     fn evact(&mut self) -> Option<V>;
+
+    /// Removes up to `limit` of the expired key-value pairs, the longest
+    /// expired first, and returns how many were removed.
+    fn remove_expired(&mut self, limit: usize) -> usize;
 }
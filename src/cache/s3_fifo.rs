@@ -0,0 +1,394 @@
+use crate::utils::doubly_linked_list::DoublyLinkedList;
+
+use super::{
+    expiry_after, Cache, Expiries, Expiring, IgnoreRemovals, Lookup, RemovalCause, RemovalListener,
+    Weigher,
+};
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::atomic::{AtomicU8, Ordering},
+    time::{Duration, Instant},
+};
+
+/// Percentage of the capacity given to the small FIFO queue.
+const SMALL_PERCENT: usize = 10;
+
+/// Highest value the access frequency of an entry saturates at.
+const MAX_FREQUENCY: u8 = 3;
+
+/// Simple, Scalable, Static FIFO cache.
+///
+/// New keys enter the small FIFO queue and only move to the main FIFO queue
+/// if they are read again before reaching its tail, so one-hit wonders leave
+/// the cache quickly. The main queue reinserts the keys which were read while
+/// in it, and the ghost queue remembers the keys recently evicted from the
+/// small queue to admit them directly into the main queue. A read only bumps
+/// a small frequency counter, so no queue is reordered on `get` and reads
+/// can be served through a shared reference.
+pub struct S3FIFOCache<K, V> {
+    capacity: usize,
+    small_capacity: usize,
+    weigher: Box<dyn Weigher<K, V>>,
+    listener: Box<dyn RemovalListener<K, V>>,
+    expiries: Expiries<K>,
+    map: HashMap<K, CacheValue<V>>,
+    small: DoublyLinkedList<K>,
+    small_weight: usize,
+    main: DoublyLinkedList<K>,
+    main_weight: usize,
+    ghost: DoublyLinkedList<K>,
+    ghost_weight: usize,
+    ghost_map: HashMap<K, GhostValue>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Queue {
+    Small,
+    Main,
+}
+
+struct CacheValue<V> {
+    value: V,
+    frequency: AtomicU8,
+    queue: Queue,
+    index: usize,
+    weight: usize,
+}
+
+struct GhostValue {
+    index: usize,
+    weight: usize,
+}
+
+impl<V> CacheValue<V> {
+    /// Records a read of the entry.
+    fn touch(&self) {
+        let _ = self
+            .frequency
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |frequency| {
+                (frequency < MAX_FREQUENCY).then_some(frequency + 1)
+            });
+    }
+}
+
+impl<K, V> S3FIFOCache<K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    fn queue(&mut self, queue: Queue) -> (&mut DoublyLinkedList<K>, &mut usize) {
+        match queue {
+            Queue::Small => (&mut self.small, &mut self.small_weight),
+            Queue::Main => (&mut self.main, &mut self.main_weight),
+        }
+    }
+
+    fn used(&self) -> usize {
+        self.small_weight + self.main_weight
+    }
+
+    /// Puts a stored key at the head of the given queue.
+    fn enqueue(&mut self, queue: Queue, key: &K, frequency: u8) {
+        let weight = match self.map.get(key) {
+            Some(entry) => entry.weight,
+            None => return,
+        };
+        let (list, queue_weight) = self.queue(queue);
+        let index = list.shift_new_growing(key.clone());
+        *queue_weight += weight;
+        let entry = self.map.get_mut(key).unwrap();
+        entry.queue = queue;
+        entry.index = index;
+        *entry.frequency.get_mut() = frequency;
+    }
+
+    /// Pops the key at the tail of the given queue, keeping it in the map.
+    fn dequeue(&mut self, queue: Queue) -> Option<K> {
+        let key = self.queue(queue).0.remove_bottom()?;
+        let weight = self.map.get(&key).map_or(0, |entry| entry.weight);
+        *self.queue(queue).1 -= weight;
+        Some(key)
+    }
+
+    /// Remembers a key evicted from the small queue, forgetting the oldest
+    /// ones once they weigh more than the main queue can hold.
+    fn push_ghost(&mut self, key: K, weight: usize) {
+        let index = self.ghost.shift_new_growing(key.clone());
+        self.ghost_weight += weight;
+        self.ghost_map.insert(key, GhostValue { index, weight });
+        while self.ghost_weight > self.capacity - self.small_capacity {
+            match self.ghost.remove_bottom() {
+                Some(oldest) => {
+                    if let Some(ghost) = self.ghost_map.remove(&oldest) {
+                        self.ghost_weight -= ghost.weight;
+                    }
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Evicts from the tail of the small queue, promoting the keys which were
+    /// read while in it to the main queue.
+    fn evict_small(&mut self) -> Option<V> {
+        while let Some(key) = self.dequeue(Queue::Small) {
+            let frequency = self.map.get(&key)?.frequency.load(Ordering::Relaxed);
+            if frequency > 0 {
+                self.enqueue(Queue::Main, &key, 0);
+            } else {
+                let evacted_entry = self.map.remove(&key)?;
+                self.expiries.remove(&key);
+                self.listener
+                    .on_removal(&key, &evacted_entry.value, RemovalCause::Evicted);
+                self.push_ghost(key, evacted_entry.weight);
+                return Some(evacted_entry.value);
+            }
+        }
+        None
+    }
+
+    /// Evicts from the tail of the main queue, giving the keys which were
+    /// read while in it another round.
+    fn evict_main(&mut self) -> Option<V> {
+        while let Some(key) = self.dequeue(Queue::Main) {
+            let frequency = self.map.get(&key)?.frequency.load(Ordering::Relaxed);
+            if frequency > 0 {
+                self.enqueue(Queue::Main, &key, frequency - 1);
+            } else {
+                let evacted_entry = self.map.remove(&key)?;
+                self.expiries.remove(&key);
+                self.listener
+                    .on_removal(&key, &evacted_entry.value, RemovalCause::Evicted);
+                return Some(evacted_entry.value);
+            }
+        }
+        None
+    }
+
+    fn evict_until_fits(&mut self, weight: usize) {
+        while self.used() + weight > self.capacity {
+            if self.evact().is_none() {
+                break;
+            }
+        }
+    }
+
+    fn insert(
+        &mut self,
+        key: K,
+        value: V,
+        expires_at: Option<Instant>,
+    ) -> Result<(), &'static str> {
+        let weight = self.weigher.weigh(&key, &value);
+        if weight > self.capacity {
+            return Err("entry is larger than the capacity of the cache");
+        }
+        self.expiries.set(&key, expires_at);
+
+        if let Some(entry) = self.map.get_mut(&key) {
+            let (queue, old_weight) = (entry.queue, entry.weight);
+            entry.value = value;
+            entry.weight = weight;
+            entry.touch();
+            let queue_weight = self.queue(queue).1;
+            *queue_weight = *queue_weight - old_weight + weight;
+            // A heavier value for an existing key can overflow the capacity
+            self.evict_until_fits(0);
+            return Ok(());
+        }
+
+        self.evict_until_fits(weight);
+        let queue = match self.ghost_map.remove(&key) {
+            Some(ghost) => {
+                self.ghost.remove(ghost.index);
+                self.ghost_weight -= ghost.weight;
+                Queue::Main
+            }
+            None => Queue::Small,
+        };
+        self.map.insert(
+            key.clone(),
+            CacheValue {
+                value,
+                frequency: AtomicU8::new(0),
+                queue,
+                index: 0,
+                weight,
+            },
+        );
+        self.enqueue(queue, &key, 0);
+        Ok(())
+    }
+}
+
+impl<K, V> Cache<K, V> for S3FIFOCache<K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    fn with_weigher(capacity: usize, weigher: Box<dyn Weigher<K, V>>) -> Self {
+        let capacity = if capacity > 1 { capacity } else { 10 };
+        let small_capacity = (capacity * SMALL_PERCENT / 100).max(1);
+        S3FIFOCache {
+            capacity,
+            small_capacity,
+            weigher,
+            listener: Box::new(IgnoreRemovals),
+            expiries: Expiries::new(),
+            map: HashMap::new(),
+            small: DoublyLinkedList::new(),
+            small_weight: 0,
+            main: DoublyLinkedList::new(),
+            main_weight: 0,
+            ghost: DoublyLinkedList::new(),
+            ghost_weight: 0,
+            ghost_map: HashMap::new(),
+        }
+    }
+
+    fn set_removal_listener(&mut self, listener: Box<dyn RemovalListener<K, V>>) {
+        self.listener = listener;
+    }
+
+    fn evact(&mut self) -> Option<V> {
+        if self.small_weight >= self.small_capacity || self.main.is_empty() {
+            if let Some(value) = self.evict_small() {
+                return Some(value);
+            }
+        }
+        self.evict_main()
+    }
+
+    fn get(&mut self, key: &K) -> Option<&V> {
+        if self.remove_if_expired(key) {
+            return None;
+        }
+        let entry = self.map.get(key)?;
+        entry.touch();
+        Some(&entry.value)
+    }
+
+    fn get_shared(&self, key: &K) -> Lookup<'_, V> {
+        match self.map.get(key) {
+            // Expired entries are removed through the exclusive path
+            Some(_) if self.expiries.is_expired(key, Instant::now()) => Lookup::Exclusive,
+            Some(entry) => {
+                entry.touch();
+                Lookup::Hit(&entry.value)
+            }
+            None => Lookup::Miss,
+        }
+    }
+
+    fn expires_at(&self, key: &K) -> Option<Instant> {
+        self.expiries.get(key)
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        self.map.contains_key(key) && !self.expiries.is_expired(key, Instant::now())
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        let entry = self.map.remove(key)?;
+        self.expiries.remove(key);
+        let (list, queue_weight) = self.queue(entry.queue);
+        list.remove(entry.index);
+        *queue_weight -= entry.weight;
+        Some(entry.value)
+    }
+
+    fn put(&mut self, key: K, value: V) -> Result<(), &'static str> {
+        self.insert(key, value, None)
+    }
+
+    fn put_with_ttl(&mut self, key: K, value: V, ttl: Duration) -> Result<(), &'static str> {
+        self.insert(key, value, expiry_after(ttl))
+    }
+
+    fn remove_expired(&mut self, limit: usize) -> usize {
+        self.remove_expired_entries(limit)
+    }
+}
+
+impl<K, V> Expiring<K, V> for S3FIFOCache<K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    fn expiries(&self) -> &Expiries<K> {
+        &self.expiries
+    }
+
+    fn listener(&self) -> &dyn RemovalListener<K, V> {
+        self.listener.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_hit_wonders_leave_first() {
+        let mut cache = S3FIFOCache::new(10);
+        for key in 1..=10 {
+            cache.put(key, key * 10).unwrap();
+        }
+        cache.get(&1);
+        cache.get(&2);
+        cache.put(11, 110).unwrap();
+        assert!(!cache.map.contains_key(&3));
+        assert!(cache.map[&1].queue == Queue::Main && cache.map[&2].queue == Queue::Main);
+        // A key evicted recently comes back straight into the main queue
+        cache.put(3, 30).unwrap();
+        assert!(cache.map[&3].queue == Queue::Main);
+        assert!(!cache.map.contains_key(&4));
+    }
+
+    #[test]
+    fn main_queue_gives_read_keys_another_round() {
+        let mut cache = S3FIFOCache::new(10);
+        for key in 1..=10 {
+            cache.put(key, key).unwrap();
+            cache.get(&key);
+        }
+        // Every key is promoted, then one is read again in the main queue
+        assert_eq!(cache.evict_small(), None);
+        assert_eq!((cache.small.len(), cache.main.len()), (0, 10));
+        cache.get(&1);
+        assert_eq!(cache.evict_main(), Some(2));
+        assert!(cache.map.contains_key(&1));
+    }
+
+    #[test]
+    fn shared_reads_count_as_accesses() {
+        let mut cache = S3FIFOCache::new(10);
+        cache.put(1, 10).unwrap();
+        cache.put_with_ttl(2, 20, Duration::ZERO).unwrap();
+        assert!(matches!(cache.get_shared(&1), Lookup::Hit(&10)));
+        assert_eq!(cache.map[&1].frequency.load(Ordering::Relaxed), 1);
+        assert!(matches!(cache.get_shared(&2), Lookup::Exclusive));
+        assert!(matches!(cache.get_shared(&3), Lookup::Miss));
+    }
+
+    #[test]
+    fn expired_entries_are_dropped() {
+        let mut cache = S3FIFOCache::new(10);
+        cache.put_with_ttl(1, 10, Duration::ZERO).unwrap();
+        cache.put_with_ttl(2, 20, Duration::from_secs(60)).unwrap();
+        assert_eq!(cache.remove_expired(usize::MAX), 1);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2), Some(&20));
+        assert_eq!(cache.map.len(), 1);
+    }
+
+    #[test]
+    fn weigher_bounds_the_entries() {
+        let weigher = |_: &u32, value: &String| value.len();
+        let mut cache = S3FIFOCache::with_weigher(10, Box::new(weigher));
+        assert!(cache.put(1, "x".repeat(11)).is_err());
+        cache.put(1, "x".repeat(4)).unwrap();
+        cache.put(2, "x".repeat(4)).unwrap();
+        cache.put(3, "x".repeat(4)).unwrap();
+        assert!(!cache.map.contains_key(&1));
+        assert_eq!(cache.used(), 8);
+    }
+}
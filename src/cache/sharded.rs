@@ -0,0 +1,74 @@
+use super::{Cache, Lookup};
+use crate::utils::hash::xxhash_64;
+use std::{hash::Hash, time::Instant};
+use tokio::sync::RwLock;
+
+/// Partitions the keys over independently locked caches, so that requests
+/// for keys in different shards do not wait on each other.
+pub struct ShardedCache<C> {
+    shards: Vec<RwLock<C>>,
+}
+
+impl<C> ShardedCache<C> {
+    /// Creates a new [`ShardedCache`] out of the given caches.
+    ///
+    /// Panics if no cache is given.
+    pub fn new(caches: Vec<C>) -> Self {
+        assert!(!caches.is_empty(), "at least one shard is required");
+        ShardedCache {
+            shards: caches.into_iter().map(RwLock::new).collect(),
+        }
+    }
+
+    /// Returns the shard that owns the given key.
+    pub fn shard<K: AsRef<[u8]> + ?Sized>(&self, key: &K) -> &RwLock<C> {
+        let index = xxhash_64(key) as usize % self.shards.len();
+        &self.shards[index]
+    }
+
+    /// Returns all the shards.
+    pub fn shards(&self) -> &[RwLock<C>] {
+        &self.shards
+    }
+
+    /// Returns all the shards, which can be accessed without locking them
+    /// since the cache is borrowed exclusively.
+    pub fn shards_mut(&mut self) -> &mut [RwLock<C>] {
+        &mut self.shards
+    }
+
+    /// Returns a copy of the value stored against the given key.
+    pub async fn get<K, V>(&self, key: &K) -> Option<V>
+    where
+        C: Cache<K, V>,
+        K: Eq + Hash + AsRef<[u8]>,
+        V: Clone,
+    {
+        self.lookup(key).await.map(|(value, _)| value)
+    }
+
+    /// Returns a copy of the value stored against the given key, along with
+    /// when it expires, both read under the same lock of the shard.
+    ///
+    /// The read is served under a shared lock of the shard when the cache
+    /// supports it, and under an exclusive lock otherwise.
+    pub async fn lookup<K, V>(&self, key: &K) -> Option<(V, Option<Instant>)>
+    where
+        C: Cache<K, V>,
+        K: Eq + Hash + AsRef<[u8]>,
+        V: Clone,
+    {
+        let shard = self.shard(key);
+        {
+            let shard = shard.read().await;
+            match shard.get_shared(key) {
+                Lookup::Hit(value) => return Some((value.clone(), shard.expires_at(key))),
+                Lookup::Miss => return None,
+                Lookup::Exclusive => {}
+            }
+        }
+        let mut shard = shard.write().await;
+        let value = shard.get(key)?.clone();
+        Some((value, shard.expires_at(key)))
+    }
+}
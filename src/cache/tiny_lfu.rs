@@ -0,0 +1,372 @@
+use crate::utils::{count_min_sketch::CountMinSketch, doubly_linked_list::DoublyLinkedList};
+
+use super::{
+    expiry_after, Cache, Expiries, Expiring, IgnoreRemovals, RemovalCause, RemovalListener, Weigher,
+};
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+/// Percentage of the capacity given to the admission window.
+const WINDOW_PERCENT: usize = 1;
+
+/// Percentage of the main space given to the protected segment.
+const PROTECTED_PERCENT: usize = 80;
+
+/// Upper bound on the capacity the frequency sketch is sized for, since a
+/// weighted capacity such as a memory budget says little about the number
+/// of entries.
+const MAX_SKETCH_CAPACITY: usize = 1 << 20;
+
+/// Window Tiny Least Frequently Used cache.
+///
+/// New entries go into a small LRU admission window. An entry leaving the
+/// window is only admitted into the segmented main LRU if the count-min
+/// sketch estimates it to be accessed more often than the entry it would
+/// evict, which keeps one-off scans from flushing the frequently used keys.
+pub struct TinyLFUCache<K, V> {
+    capacity: usize,
+    weigher: Box<dyn Weigher<K, V>>,
+    listener: Box<dyn RemovalListener<K, V>>,
+    expiries: Expiries<K>,
+    map: HashMap<K, CacheValue<V>>,
+    sketch: CountMinSketch,
+    window: DoublyLinkedList<K>,
+    window_weight: usize,
+    window_capacity: usize,
+    probation: DoublyLinkedList<K>,
+    probation_weight: usize,
+    protected: DoublyLinkedList<K>,
+    protected_weight: usize,
+    protected_capacity: usize,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Segment {
+    Window,
+    Probation,
+    Protected,
+}
+
+struct CacheValue<V> {
+    value: V,
+    segment: Segment,
+    index: usize,
+    weight: usize,
+}
+
+impl<K, V> TinyLFUCache<K, V>
+where
+    K: Eq + Hash + Clone + AsRef<[u8]>,
+{
+    fn main_capacity(&self) -> usize {
+        self.capacity - self.window_capacity
+    }
+
+    fn main_weight(&self) -> usize {
+        self.probation_weight + self.protected_weight
+    }
+
+    fn segment(&mut self, segment: Segment) -> (&mut DoublyLinkedList<K>, &mut usize) {
+        match segment {
+            Segment::Window => (&mut self.window, &mut self.window_weight),
+            Segment::Probation => (&mut self.probation, &mut self.probation_weight),
+            Segment::Protected => (&mut self.protected, &mut self.protected_weight),
+        }
+    }
+
+    /// Puts a stored key at the head of the given segment.
+    fn link(&mut self, segment: Segment, key: &K) {
+        let weight = match self.map.get(key) {
+            Some(entry) => entry.weight,
+            None => return,
+        };
+        let (list, segment_weight) = self.segment(segment);
+        let index = list.shift_new_growing(key.clone());
+        *segment_weight += weight;
+        let entry = self.map.get_mut(key).unwrap();
+        entry.segment = segment;
+        entry.index = index;
+    }
+
+    /// Takes a stored key out of its segment, keeping it in the map.
+    fn unlink(&mut self, key: &K) {
+        let (segment, index, weight) = match self.map.get(key) {
+            Some(entry) => (entry.segment, entry.index, entry.weight),
+            None => return,
+        };
+        let (list, segment_weight) = self.segment(segment);
+        list.remove(index);
+        *segment_weight -= weight;
+    }
+
+    fn evict(&mut self, key: &K) -> Option<V> {
+        let value = self.remove(key)?;
+        self.listener.on_removal(key, &value, RemovalCause::Evicted);
+        Some(value)
+    }
+
+    /// Records an access of an already stored key.
+    fn touch(&mut self, key: &K) {
+        self.sketch.increment(key);
+        let (segment, index) = match self.map.get(key) {
+            Some(entry) => (entry.segment, entry.index),
+            None => return,
+        };
+        match segment {
+            Segment::Window | Segment::Protected => self.segment(segment).0.shift(index),
+            Segment::Probation => {
+                self.unlink(key);
+                self.link(Segment::Protected, key);
+                // Demote the least recently used protected keys to make room
+                while self.protected_weight > self.protected_capacity {
+                    match self.protected.bottom().cloned() {
+                        Some(demoted) => {
+                            self.unlink(&demoted);
+                            self.link(Segment::Probation, &demoted);
+                        }
+                        None => break,
+                    }
+                }
+            }
+        }
+    }
+
+    /// Moves the least recently used key out of the overflowing window into
+    /// the main space, and lets it compete with the main space victims until
+    /// the main space fits in its capacity again.
+    fn evict_from_window(&mut self) {
+        let candidate = match self.window.bottom().cloned() {
+            Some(candidate) => candidate,
+            None => return,
+        };
+        self.unlink(&candidate);
+        self.link(Segment::Probation, &candidate);
+
+        while self.main_weight() > self.main_capacity() {
+            let victim = self.probation.bottom().or(self.protected.bottom()).cloned();
+            match victim {
+                Some(victim)
+                    if victim != candidate
+                        && self.sketch.frequency(&candidate) > self.sketch.frequency(&victim) =>
+                {
+                    self.evict(&victim);
+                }
+                _ => {
+                    self.evict(&candidate);
+                    // The main space may still overflow if the candidate
+                    // was lighter than the victims, so evict them outright
+                    while self.main_weight() > self.main_capacity() {
+                        if self.evact().is_none() {
+                            break;
+                        }
+                    }
+                    return;
+                }
+            }
+        }
+    }
+
+    fn insert(
+        &mut self,
+        key: K,
+        value: V,
+        expires_at: Option<Instant>,
+    ) -> Result<(), &'static str> {
+        let weight = self.weigher.weigh(&key, &value);
+        // Every entry has to end up in the main space to stay in the cache
+        if weight > self.main_capacity() {
+            return Err("entry is larger than the capacity of the cache");
+        }
+        self.expiries.set(&key, expires_at);
+
+        if self.map.contains_key(&key) {
+            self.unlink(&key);
+            let entry = self.map.get_mut(&key).unwrap();
+            let segment = entry.segment;
+            entry.value = value;
+            entry.weight = weight;
+            self.link(segment, &key);
+            self.touch(&key);
+        } else {
+            self.sketch.increment(&key);
+            self.map.insert(
+                key.clone(),
+                CacheValue {
+                    value,
+                    segment: Segment::Window,
+                    index: 0,
+                    weight,
+                },
+            );
+            self.link(Segment::Window, &key);
+        }
+
+        while self.window_weight > self.window_capacity {
+            self.evict_from_window();
+        }
+        while self.main_weight() > self.main_capacity() {
+            if self.evact().is_none() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<K, V> Cache<K, V> for TinyLFUCache<K, V>
+where
+    K: Eq + Hash + Clone + AsRef<[u8]>,
+{
+    fn with_weigher(capacity: usize, weigher: Box<dyn Weigher<K, V>>) -> Self {
+        let capacity = if capacity > 1 { capacity } else { 10 };
+        let window_capacity = (capacity * WINDOW_PERCENT / 100).max(1);
+        let main_capacity = capacity - window_capacity;
+        let protected_capacity = (main_capacity * PROTECTED_PERCENT / 100).max(1);
+        TinyLFUCache {
+            capacity,
+            weigher,
+            listener: Box::new(IgnoreRemovals),
+            expiries: Expiries::new(),
+            map: HashMap::new(),
+            sketch: CountMinSketch::with_capacity(capacity.min(MAX_SKETCH_CAPACITY)),
+            window: DoublyLinkedList::new(),
+            window_weight: 0,
+            window_capacity,
+            probation: DoublyLinkedList::new(),
+            probation_weight: 0,
+            protected: DoublyLinkedList::new(),
+            protected_weight: 0,
+            protected_capacity,
+        }
+    }
+
+    fn set_removal_listener(&mut self, listener: Box<dyn RemovalListener<K, V>>) {
+        self.listener = listener;
+    }
+
+    fn evact(&mut self) -> Option<V> {
+        let victim = self
+            .probation
+            .bottom()
+            .or(self.protected.bottom())
+            .or(self.window.bottom())
+            .cloned()?;
+        self.evict(&victim)
+    }
+
+    fn get(&mut self, key: &K) -> Option<&V> {
+        if self.remove_if_expired(key) {
+            return None;
+        }
+        self.touch(key);
+        self.map.get(key).map(|entry| &entry.value)
+    }
+
+    fn expires_at(&self, key: &K) -> Option<Instant> {
+        self.expiries.get(key)
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        self.map.contains_key(key) && !self.expiries.is_expired(key, Instant::now())
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        self.unlink(key);
+        self.expiries.remove(key);
+        self.map.remove(key).map(|entry| entry.value)
+    }
+
+    fn put(&mut self, key: K, value: V) -> Result<(), &'static str> {
+        self.insert(key, value, None)
+    }
+
+    fn put_with_ttl(&mut self, key: K, value: V, ttl: Duration) -> Result<(), &'static str> {
+        self.insert(key, value, expiry_after(ttl))
+    }
+
+    fn remove_expired(&mut self, limit: usize) -> usize {
+        self.remove_expired_entries(limit)
+    }
+}
+
+impl<K, V> Expiring<K, V> for TinyLFUCache<K, V>
+where
+    K: Eq + Hash + Clone + AsRef<[u8]>,
+{
+    fn expiries(&self) -> &Expiries<K> {
+        &self.expiries
+    }
+
+    fn listener(&self) -> &dyn RemovalListener<K, V> {
+        self.listener.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(name: &str, i: usize) -> String {
+        format!("{name}-{i}")
+    }
+
+    #[test]
+    fn scans_do_not_flush_frequent_keys() {
+        let mut cache = TinyLFUCache::new(100);
+        for i in 0..20 {
+            cache.put(key("hot", i), i).unwrap();
+        }
+        for _ in 0..10 {
+            for i in 0..20 {
+                cache.get(&key("hot", i));
+            }
+        }
+        // A scan of three times the capacity, which the sketch is sized for
+        for i in 0..300 {
+            cache.put(key("cold", i), i).unwrap();
+        }
+        assert!((0..20).all(|i| cache.map.contains_key(&key("hot", i))));
+        assert!(cache.map.len() <= 100);
+        assert!(cache.window.len() <= cache.window_capacity);
+    }
+
+    #[test]
+    fn reads_promote_to_the_protected_segment() {
+        let mut cache = TinyLFUCache::new(100);
+        cache.put(key("a", 0), 0).unwrap();
+        cache.put(key("b", 0), 0).unwrap();
+        assert!(cache.map[&key("a", 0)].segment == Segment::Probation);
+        assert!(cache.map[&key("b", 0)].segment == Segment::Window);
+        cache.get(&key("a", 0));
+        assert!(cache.map[&key("a", 0)].segment == Segment::Protected);
+    }
+
+    #[test]
+    fn expired_entries_are_dropped() {
+        let mut cache = TinyLFUCache::new(100);
+        cache.put_with_ttl(key("a", 0), 0, Duration::ZERO).unwrap();
+        cache
+            .put_with_ttl(key("b", 0), 1, Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(cache.remove_expired(usize::MAX), 1);
+        assert_eq!(cache.get(&key("a", 0)), None);
+        assert_eq!(cache.get(&key("b", 0)), Some(&1));
+        assert_eq!(cache.map.len(), 1);
+    }
+
+    #[test]
+    fn weigher_bounds_the_entries() {
+        let weigher = |_: &String, value: &String| value.len();
+        let mut cache = TinyLFUCache::with_weigher(100, Box::new(weigher));
+        // Every entry has to fit in the main space
+        assert!(cache.put(key("a", 0), "x".repeat(100)).is_err());
+        cache.put(key("a", 0), "x".repeat(99)).unwrap();
+        cache.put(key("b", 0), "x".repeat(50)).unwrap();
+        cache.put(key("c", 0), "x".repeat(50)).unwrap();
+        assert!(cache.window_weight + cache.main_weight() <= 100);
+        assert!(cache.main_weight() <= cache.main_capacity());
+    }
+}
@@ -1,9 +1,23 @@
 use actix_web::{web, App, HttpServer};
-use cache::Cache;
-use network::CacheNetwork;
-use rpc::{Entry, GetResponse, Key, PingRequest, Pong, PongResponse, PutResponse, Value};
+use bytes::Bytes;
+use cache::{sharded::ShardedCache, Cache, RemovalCause, Versioned};
+use futures_util::{stream, Stream, StreamExt};
+use network::{CacheNetwork, SubscribeStream, WatchStream};
+use rpc::{
+    ChannelMessage, CompareAndSetRequest, CompareAndSetResponse, CounterRequest, CounterResponse,
+    DeleteResponse, Entry, EventKind, GetResponse, Key, MultiGetRequest, MultiGetResponse,
+    MultiPutRequest, MultiPutResponse, PingRequest, Pong, PongResponse, PublishRequest,
+    PublishResponse, PutResponse, SubscribeRequest, Value, WatchEvent, WatchRequest, WriteMode,
+};
+use std::collections::HashMap;
 use std::error::Error;
 use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::net::TcpListener;
+use tokio::sync::broadcast::{self, error::RecvError};
 use tokio::sync::Mutex;
 use tonic::{async_trait, Request, Response, Result, Status};
 use utils::http;
@@ -18,17 +32,21 @@ pub mod utils;
 
 pub enum RPCServer {}
 pub enum HTTPServer {}
+pub enum RESPServer {}
+pub enum MemcachedServer {}
 
 pub trait Server {}
 impl Server for RPCServer {}
 impl Server for HTTPServer {}
+impl Server for RESPServer {}
+impl Server for MemcachedServer {}
 
 /// RPC server for the Cache Cluster
 pub struct CacheClusterServer<T = RPCServer>
 where
     T: Server,
 {
-    network: Mutex<CacheNetwork>,
+    network: Arc<Mutex<CacheNetwork>>,
     pd: PhantomData<T>,
 }
 
@@ -38,17 +56,26 @@ where
 {
     pub fn new(network: CacheNetwork) -> Self {
         Self {
-            network: Mutex::new(network),
+            network: Arc::new(Mutex::new(network)),
+            pd: PhantomData,
+        }
+    }
+
+    /// Returns a server of another kind over the same network, so that the
+    /// cluster can be served over several protocols at once.
+    pub fn share<U: Server>(&self) -> CacheClusterServer<U> {
+        CacheClusterServer {
+            network: Arc::clone(&self.network),
             pd: PhantomData,
         }
     }
 }
 
 impl CacheClusterServer {
-    pub async fn run(mut self, addr: &str) -> Result<(), Box<dyn Error>> {
+    pub async fn run(self, addr: &str) -> Result<(), Box<dyn Error>> {
         use rpc::cluster_server::ClusterServer;
         use tonic::transport::Server;
-        self.network.get_mut().connect_nodes().await?;
+        self.network.lock().await.connect_nodes().await?;
         Server::builder()
             .add_service(ClusterServer::new(self))
             .serve(addr.parse().unwrap())
@@ -68,17 +95,82 @@ impl rpc::cluster_server::Cluster for CacheClusterServer {
         let entry = entry.into_inner();
         self.network.lock().await.put_entry(entry).await
     }
+
+    async fn delete(&self, key: Request<Key>) -> Result<Response<DeleteResponse>> {
+        let key = key.into_inner();
+        self.network.lock().await.delete_entry(key).await
+    }
+
+    async fn multi_get(
+        &self,
+        request: Request<MultiGetRequest>,
+    ) -> Result<Response<MultiGetResponse>> {
+        let keys = request.into_inner().keys;
+        self.network.lock().await.multi_get(keys).await
+    }
+
+    async fn multi_put(
+        &self,
+        request: Request<MultiPutRequest>,
+    ) -> Result<Response<MultiPutResponse>> {
+        let entries = request.into_inner().entries;
+        self.network.lock().await.multi_put(entries).await
+    }
+
+    async fn compare_and_set(
+        &self,
+        request: Request<CompareAndSetRequest>,
+    ) -> Result<Response<CompareAndSetResponse>> {
+        let request = request.into_inner();
+        self.network.lock().await.compare_and_set(request).await
+    }
+
+    async fn incr(&self, request: Request<CounterRequest>) -> Result<Response<CounterResponse>> {
+        let request = request.into_inner();
+        self.network.lock().await.incr(request).await
+    }
+
+    async fn decr(&self, request: Request<CounterRequest>) -> Result<Response<CounterResponse>> {
+        let request = request.into_inner();
+        self.network.lock().await.decr(request).await
+    }
+
+    type WatchStream = WatchStream;
+
+    async fn watch(&self, request: Request<WatchRequest>) -> Result<Response<WatchStream>> {
+        let request = request.into_inner();
+        self.network.lock().await.watch(request).await
+    }
+
+    async fn publish(&self, request: Request<PublishRequest>) -> Result<Response<PublishResponse>> {
+        let request = request.into_inner();
+        self.network.lock().await.publish(request).await
+    }
+
+    type SubscribeStream = SubscribeStream;
+
+    async fn subscribe(
+        &self,
+        request: Request<SubscribeRequest>,
+    ) -> Result<Response<SubscribeStream>> {
+        let request = request.into_inner();
+        self.network.lock().await.subscribe(request).await
+    }
 }
 
 impl CacheClusterServer<HTTPServer> {
     pub async fn run(self, addr: &str) -> Result<(), Box<dyn Error>> {
+        self.network.lock().await.connect_nodes().await?;
         let cluster_data = web::Data::new(self);
 
         HttpServer::new(move || {
             App::new()
                 .app_data(cluster_data.clone())
+                .configure(http::limits)
                 .service(http::cluster::get)
                 .service(http::cluster::save)
+                .service(http::cluster::save_raw)
+                .service(http::cluster::remove)
         })
         .bind(addr)?
         .run()
@@ -88,78 +180,777 @@ impl CacheClusterServer<HTTPServer> {
     }
 }
 
+/// How often the [`CacheServer`] sweeps the cache for expired entries.
+const EXPIRY_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Most expired entries removed from a shard under a single lock, so that
+/// sweeping a shard full of expired entries does not hold up its requests.
+const EXPIRY_SWEEP_BATCH: usize = 128;
+
+/// Bits of a version below the milliseconds of the wall clock the versions
+/// start from.
+const VERSION_CLOCK_SHIFT: u32 = 20;
+
+/// Returns the version the wall clock is at, which the versions of a node
+/// start after so that they keep growing across restarts and a version handed
+/// out before a restart is never handed out again. This holds as long as a
+/// node hands out fewer than a million versions per millisecond it runs. The
+/// cluster stamps its writes from the same clock, so that the versions of all
+/// the nodes can be compared.
+pub(crate) fn clock_version() -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    (now.as_millis() as u64) << VERSION_CLOCK_SHIFT
+}
+
+/// Number of events a watcher, or of messages a subscriber, can fall behind
+/// by before it misses some.
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// Streams the values sent to the receiver that pass the filter. Falling
+/// behind yields an error telling how many values were missed, after which
+/// the stream goes on with the next ones, and the stream ends once the
+/// sender is dropped.
+fn broadcast_stream<T, F>(
+    receiver: broadcast::Receiver<T>,
+    filter: F,
+) -> Pin<Box<dyn Stream<Item = Result<T>> + Send>>
+where
+    T: Clone + Send + 'static,
+    F: Fn(&T) -> bool + Send + 'static,
+{
+    stream::unfold((receiver, filter), |(mut receiver, filter)| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(value) if filter(&value) => return Some((Ok(value), (receiver, filter))),
+                Ok(_) => {}
+                Err(RecvError::Lagged(missed)) => {
+                    let msg = format!("fell behind and missed {missed} messages");
+                    return Some((Err(Status::data_loss(msg)), (receiver, filter)));
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+    .boxed()
+}
+
+impl WatchRequest {
+    fn matches(&self, key: &[u8]) -> bool {
+        if self.prefix {
+            key.starts_with(&self.key)
+        } else {
+            key == self.key
+        }
+    }
+}
+
+fn watch_event(kind: EventKind, key: &[u8], value: &Versioned<Bytes>) -> WatchEvent {
+    WatchEvent {
+        kind: kind.into(),
+        key: Some(Key { key: key.to_vec() }),
+        value: Some(Value {
+            value: value.value.clone(),
+        }),
+        version: value.version,
+    }
+}
+
+/// Converts a time to live in milliseconds, where `0` means the entry never
+/// expires.
+fn ttl_from_millis(ttl: u64) -> Option<Duration> {
+    (ttl > 0).then(|| Duration::from_millis(ttl))
+}
+
+/// Converts a time to live to milliseconds, rounding up so that an entry
+/// about to expire is not mistaken for one which never expires.
+fn ttl_to_millis(ttl: Option<Duration>) -> u64 {
+    ttl.map_or(0, |ttl| (ttl.as_millis() as u64).max(1))
+}
+
+fn get_response((stored, ttl): (Versioned<Bytes>, Option<Duration>)) -> GetResponse {
+    GetResponse {
+        value: Some(Value {
+            value: stored.value,
+        }),
+        version: stored.version,
+        ttl: ttl_to_millis(ttl),
+    }
+}
+
+/// Condition an entry is stored under, whatever the protocol it is written
+/// over.
+#[derive(Clone, Copy)]
+pub(crate) enum WriteCondition {
+    Always,
+    /// Only if the key is not present
+    Absent,
+    /// Only if the key is present
+    Present,
+    /// Only if the key is present at the given version
+    Version(u64),
+}
+
+/// Why an entry was not stored.
+pub(crate) enum StoreError {
+    /// The key is present, at the given version, while the condition does
+    /// not allow it
+    Exists(u64),
+    /// The key is not present while the condition requires it
+    NotFound,
+    /// The entry does not fit in the cache
+    TooLarge(&'static str),
+    /// The key is present at the given version, which is not older than the
+    /// version the write was stamped with
+    Stale(u64),
+}
+
+/// How a counter behaves once counting would take it out of the range of
+/// its values.
+#[derive(Clone, Copy)]
+pub(crate) enum Overflow {
+    /// The counter is a signed 64-bit integer, which is refused to overflow
+    Checked,
+    /// The counter is an unsigned 64-bit integer, where increments wrap
+    /// around while decrements stop at 0, like memcached does
+    Wrap,
+}
+
+impl Overflow {
+    /// Reads a stored counter, if it is an integer in the range of the
+    /// counters.
+    fn parse(self, value: &[u8]) -> Option<i128> {
+        let value = std::str::from_utf8(value).ok()?;
+        match self {
+            Overflow::Checked => value.parse::<i64>().ok().map(i128::from),
+            Overflow::Wrap => value.parse::<u64>().ok().map(i128::from),
+        }
+    }
+
+    /// Adds the delta to the counter, or subtracts it if `increment` is
+    /// false, unless the counter would overflow.
+    fn count(self, current: i128, delta: u64, increment: bool) -> Option<i128> {
+        match self {
+            Overflow::Checked => {
+                let current = i64::try_from(current).ok()?;
+                let delta = i64::try_from(delta).ok()?;
+                if increment {
+                    current.checked_add(delta)
+                } else {
+                    current.checked_sub(delta)
+                }
+                .map(i128::from)
+            }
+            Overflow::Wrap => {
+                let current = u64::try_from(current).ok()?;
+                let value = if increment {
+                    current.wrapping_add(delta)
+                } else {
+                    current.saturating_sub(delta)
+                };
+                Some(value.into())
+            }
+        }
+    }
+}
+
+/// Why a counter was not counted.
+pub(crate) enum CountError {
+    /// The key is not present and no counter is to be created
+    NotFound,
+    /// The stored value is not an integer in the range of the counters
+    NotNumeric,
+    /// The counter would overflow
+    Overflow,
+    /// The counter does not fit in the cache
+    TooLarge(&'static str),
+}
+
+/// A counted counter.
+pub(crate) struct Counted {
+    pub(crate) value: i128,
+    pub(crate) version: u64,
+    /// Whether the counter was created rather than counted
+    pub(crate) created: bool,
+}
+
+/// Senders of the Pub/Sub channels by name.
+type Channels = Arc<std::sync::Mutex<HashMap<Vec<u8>, broadcast::Sender<ChannelMessage>>>>;
+
+/// Messages of a channel to a subscriber, which removes the channel once the
+/// last subscriber leaves.
+struct Subscription {
+    messages: SubscribeStream,
+    channel: Vec<u8>,
+    channels: Channels,
+}
+
+impl Stream for Subscription {
+    type Item = Result<ChannelMessage>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        self.messages.poll_next_unpin(cx)
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        // Drops the receiver first, so that it no longer counts
+        self.messages = stream::empty().boxed();
+        let mut channels = self.channels.lock().unwrap();
+        if channels
+            .get(&self.channel)
+            .is_some_and(|sender| sender.receiver_count() == 0)
+        {
+            channels.remove(&self.channel);
+        }
+    }
+}
+
 /// RPC server for the Cache
 pub struct CacheServer<C, T = RPCServer>
 where
-    C: Cache<String, String>,
+    C: Cache<Vec<u8>, Versioned<Bytes>>,
     T: Server,
 {
-    cache: Mutex<C>,
+    cache: ShardedCache<C>,
+    /// Last version handed out to a write, shared by all the shards so that
+    /// a key never gets a version it had before, even across restarts
+    version: AtomicU64,
+    /// Changes of the entries, sent to every watcher
+    events: broadcast::Sender<WatchEvent>,
+    /// Pub/Sub channels which have subscribers
+    channels: Channels,
     pd: PhantomData<T>,
 }
 
 impl<C, T> CacheServer<C, T>
 where
-    C: Cache<String, String> + Send + 'static,
+    C: Cache<Vec<u8>, Versioned<Bytes>> + Send + Sync + 'static,
     T: Server,
 {
-    fn new(cache: C) -> Self {
+    fn new(mut cache: ShardedCache<C>) -> Self {
+        let (events, _) = broadcast::channel(CHANNEL_CAPACITY);
+        for shard in cache.shards_mut() {
+            let events = events.clone();
+            shard.get_mut().set_removal_listener(Box::new(
+                move |key: &Vec<u8>, value: &Versioned<Bytes>, cause| {
+                    if events.receiver_count() > 0 {
+                        let kind = match cause {
+                            RemovalCause::Evicted => EventKind::Evict,
+                            RemovalCause::Expired => EventKind::Expire,
+                        };
+                        let _ = events.send(watch_event(kind, key, value));
+                    }
+                },
+            ));
+        }
         Self {
-            cache: Mutex::new(cache),
+            cache,
+            version: AtomicU64::new(clock_version()),
+            events,
+            channels: Arc::new(std::sync::Mutex::new(HashMap::new())),
             pd: PhantomData,
         }
     }
+
+    /// Stores the entry in the given shard under the version the cluster
+    /// stamped it with, or a new version of the node if it is not stamped.
+    fn store(
+        &self,
+        shard: &mut C,
+        key: Vec<u8>,
+        value: Bytes,
+        flags: u32,
+        ttl: Option<Duration>,
+        stamp: Option<u64>,
+    ) -> Result<u64, &'static str> {
+        let version = match stamp {
+            Some(stamp) => {
+                // The versions of the node keep above the stamped ones
+                self.version.fetch_max(stamp, Ordering::Relaxed);
+                stamp
+            }
+            None => self.version.fetch_add(1, Ordering::Relaxed) + 1,
+        };
+        let value = Versioned {
+            value,
+            version,
+            flags,
+        };
+        let event =
+            (self.events.receiver_count() > 0).then(|| watch_event(EventKind::Put, &key, &value));
+        match ttl {
+            Some(ttl) => shard.put_with_ttl(key, value, ttl)?,
+            None => shard.put(key, value)?,
+        }
+        if let Some(event) = event {
+            let _ = self.events.send(event);
+        }
+        Ok(version)
+    }
+
+    /// Stores the entry if the condition holds for the key, checking it
+    /// under the same lock of the shard, and returns the new version of the
+    /// entry. An entry stamped by the cluster at a version older than the
+    /// stored one is left out, as if it had been overwritten since.
+    async fn store_if(
+        &self,
+        condition: WriteCondition,
+        key: Vec<u8>,
+        value: Bytes,
+        flags: u32,
+        ttl: Option<Duration>,
+        stamp: Option<u64>,
+    ) -> std::result::Result<u64, StoreError> {
+        let mut cache = self.cache.shard(&key).write().await;
+        let current = cache.get(&key).map(|stored| stored.version);
+        match (condition, current) {
+            (WriteCondition::Absent, Some(current)) => return Err(StoreError::Exists(current)),
+            (WriteCondition::Present | WriteCondition::Version(_), None) => {
+                return Err(StoreError::NotFound)
+            }
+            (WriteCondition::Version(version), Some(current)) if version != current => {
+                return Err(StoreError::Exists(current))
+            }
+            _ => {}
+        }
+        if let (Some(stamp), Some(current)) = (stamp, current) {
+            if current >= stamp {
+                return Err(StoreError::Stale(current));
+            }
+        }
+        self.store(&mut cache, key, value, flags, ttl, stamp)
+            .map_err(StoreError::TooLarge)
+    }
+
+    /// Stores the entry according to its write mode and returns its version.
+    async fn put_entry(&self, entry: Entry) -> Result<u64> {
+        let condition = match entry.mode() {
+            WriteMode::Set => WriteCondition::Always,
+            WriteMode::Add => WriteCondition::Absent,
+            WriteMode::Replace => WriteCondition::Present,
+        };
+        if let (Some(key), Some(value)) = (entry.key, entry.value) {
+            let ttl = ttl_from_millis(entry.ttl);
+            let stamp = (entry.version > 0).then_some(entry.version);
+            match self
+                .store_if(condition, key.key, value.value, 0, ttl, stamp)
+                .await
+            {
+                Ok(version) => Ok(version),
+                Err(StoreError::Exists(_)) => Err(Status::already_exists("key already exists")),
+                Err(StoreError::NotFound) => Err(Status::not_found("key not found")),
+                Err(StoreError::TooLarge(msg)) => Err(Status::internal(msg)),
+                Err(StoreError::Stale(_)) => Err(Status::aborted("a newer version is stored")),
+            }
+        } else {
+            Err(Status::invalid_argument("key or value not valid!"))
+        }
+    }
+
+    /// Removes the entry and returns its value.
+    async fn delete_entry(&self, key: Vec<u8>) -> Option<Versioned<Bytes>> {
+        let value = self.cache.shard(&key).write().await.remove(&key)?;
+        if self.events.receiver_count() > 0 {
+            let _ = self
+                .events
+                .send(watch_event(EventKind::Delete, &key, &value));
+        }
+        Some(value)
+    }
+
+    /// Looks up the entry along with the time it has left to live, if it
+    /// expires. Both are read under the same lock of the shard, so that the
+    /// time to live is the one of the value returned.
+    async fn lookup(&self, key: &Vec<u8>) -> Option<(Versioned<Bytes>, Option<Duration>)> {
+        let (value, expires_at) = self.cache.lookup(key).await?;
+        let ttl = expires_at.map(|expires_at| expires_at.saturating_duration_since(Instant::now()));
+        Some((value, ttl))
+    }
+
+    /// Gives the entry a new time to live, or makes it never expire, keeping
+    /// its value and version. Returns whether the key is present.
+    async fn touch(&self, key: Vec<u8>, ttl: Option<Duration>) -> bool {
+        let mut cache = self.cache.shard(&key).write().await;
+        let value = match cache.get(&key) {
+            Some(value) => value.clone(),
+            None => return false,
+        };
+        let result = match ttl {
+            Some(ttl) => cache.put_with_ttl(key, value, ttl),
+            None => cache.put(key, value),
+        };
+        result.is_ok()
+    }
+
+    /// Atomically adds the delta to the counter, or subtracts it if
+    /// `increment` is false. A new counter starts from the initial value of
+    /// the request, while an existing one keeps its expiry and flags.
+    /// Adds the delta to the counter stored under the key, or subtracts it
+    /// if `increment` is false, keeping the flags and expiry of the counter.
+    /// A missing counter is created with the given value and time to live,
+    /// if any, rather than counted.
+    pub(crate) async fn add_to_counter(
+        &self,
+        key: Vec<u8>,
+        delta: u64,
+        increment: bool,
+        missing: Option<(i128, Option<Duration>)>,
+        overflow: Overflow,
+    ) -> std::result::Result<Counted, CountError> {
+        let mut cache = self.cache.shard(&key).write().await;
+        let (value, flags, ttl, created) = match cache.get(&key) {
+            Some(stored) => {
+                let current = overflow
+                    .parse(&stored.value)
+                    .ok_or(CountError::NotNumeric)?;
+                let value = overflow
+                    .count(current, delta, increment)
+                    .ok_or(CountError::Overflow)?;
+                let flags = stored.flags;
+                let ttl = cache
+                    .expires_at(&key)
+                    .map(|expires_at| expires_at.saturating_duration_since(Instant::now()));
+                (value, flags, ttl, false)
+            }
+            None => {
+                let (value, ttl) = missing.ok_or(CountError::NotFound)?;
+                (value, 0, ttl, true)
+            }
+        };
+        let version = self
+            .store(&mut cache, key, value.to_string().into(), flags, ttl, None)
+            .map_err(CountError::TooLarge)?;
+        Ok(Counted {
+            value,
+            version,
+            created,
+        })
+    }
+
+    async fn count(
+        &self,
+        request: CounterRequest,
+        increment: bool,
+    ) -> Result<Response<CounterResponse>> {
+        let CounterRequest {
+            key,
+            delta,
+            initial,
+            ttl,
+        } = request;
+        let key = match key {
+            Some(key) => key.key,
+            None => return Err(Status::invalid_argument("key not given")),
+        };
+        if i64::try_from(delta).is_err() {
+            return Err(Status::out_of_range("delta is too large"));
+        }
+
+        // A missing counter is counted from the initial value, and is only
+        // left missing when that overflows
+        let missing = Overflow::Checked
+            .count(initial.into(), delta, increment)
+            .map(|value| (value, ttl_from_millis(ttl)));
+        match self
+            .add_to_counter(key, delta, increment, missing, Overflow::Checked)
+            .await
+        {
+            Ok(Counted { value, version, .. }) => Ok(Response::new(CounterResponse {
+                value: value as i64,
+                version,
+            })),
+            Err(CountError::NotNumeric) => {
+                Err(Status::failed_precondition("value is not an integer"))
+            }
+            Err(CountError::NotFound | CountError::Overflow) => {
+                Err(Status::out_of_range("counter would overflow"))
+            }
+            Err(CountError::TooLarge(msg)) => Err(Status::internal(msg)),
+        }
+    }
+
+    /// Periodically removes the expired entries, so that the entries which
+    /// are never read again do not hold on to the cache capacity. Only the
+    /// expired entries are visited, a batch at a time.
+    fn spawn_expiry_sweeper(self: &Arc<Self>)
+    where
+        T: Send + Sync + 'static,
+    {
+        let server = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(EXPIRY_SWEEP_INTERVAL);
+            loop {
+                interval.tick().await;
+                for shard in server.cache.shards() {
+                    // The lock is released between the batches
+                    while shard.write().await.remove_expired(EXPIRY_SWEEP_BATCH)
+                        == EXPIRY_SWEEP_BATCH
+                    {
+                        tokio::task::yield_now().await;
+                    }
+                }
+            }
+        });
+    }
 }
 
 impl<C> CacheServer<C>
 where
-    C: Cache<String, String> + Send + 'static,
+    C: Cache<Vec<u8>, Versioned<Bytes>> + Send + Sync + 'static,
 {
-    pub async fn run(addr: &str, cache: C) -> Result<(), Box<dyn Error>> {
-        let service = Self::new(cache);
+    pub async fn run(addr: &str, cache: ShardedCache<C>) -> Result<(), Box<dyn Error>> {
+        let service = Arc::new(Self::new(cache));
+        service.spawn_expiry_sweeper();
         let addr = addr.parse().unwrap();
         use rpc::cache_server::CacheServer;
         use tonic::transport::Server;
         Server::builder()
-            .add_service(CacheServer::new(service))
+            .add_service(CacheServer::from_arc(service))
             .serve(addr)
             .await?;
         Ok(())
     }
 }
 
+impl<C> CacheServer<C, RESPServer>
+where
+    C: Cache<Vec<u8>, Versioned<Bytes>> + Send + Sync + 'static,
+{
+    /// Serves the cache over the Redis protocol, so that Redis clients can
+    /// talk to the node.
+    pub async fn run(addr: &str, cache: ShardedCache<C>) -> Result<(), Box<dyn Error>> {
+        let server = Arc::new(Self::new(cache));
+        server.spawn_expiry_sweeper();
+        let listener = TcpListener::bind(addr).await?;
+        loop {
+            let (socket, _) = listener.accept().await?;
+            let server = Arc::clone(&server);
+            tokio::spawn(async move {
+                // A failing connection only concerns its own client
+                let _ = utils::resp::cache::serve(server, socket).await;
+            });
+        }
+    }
+}
+
+impl<C> CacheServer<C, HTTPServer>
+where
+    C: Cache<Vec<u8>, Versioned<Bytes>> + Send + Sync + 'static,
+{
+    /// Serves the cache over a REST API, so that a single node can be used
+    /// over HTTP without a cluster in front of it.
+    pub async fn run(addr: &str, cache: ShardedCache<C>) -> Result<(), Box<dyn Error>> {
+        let server = Arc::new(Self::new(cache));
+        server.spawn_expiry_sweeper();
+        let server_data = web::Data::from(server);
+
+        HttpServer::new(move || {
+            App::new()
+                .app_data(server_data.clone())
+                .configure(http::cache::configure::<C>)
+        })
+        .bind(addr)?
+        .run()
+        .await?;
+
+        Ok(())
+    }
+}
+
+impl<C> CacheServer<C, MemcachedServer>
+where
+    C: Cache<Vec<u8>, Versioned<Bytes>> + Send + Sync + 'static,
+{
+    /// Serves the cache over the text and binary protocols of memcached, so
+    /// that memcached clients can talk to the node.
+    pub async fn run(addr: &str, cache: ShardedCache<C>) -> Result<(), Box<dyn Error>> {
+        let server = Arc::new(Self::new(cache));
+        server.spawn_expiry_sweeper();
+        let stats = Arc::new(utils::memcached::Stats::default());
+        let listener = TcpListener::bind(addr).await?;
+        loop {
+            let (socket, _) = listener.accept().await?;
+            let server = Arc::clone(&server);
+            let stats = Arc::clone(&stats);
+            tokio::spawn(async move {
+                // A failing connection only concerns its own client
+                let _ = utils::memcached::cache::serve(server, stats, socket).await;
+            });
+        }
+    }
+}
+
 #[async_trait]
 impl<C> rpc::cache_server::Cache for CacheServer<C>
 where
-    C: Cache<String, String> + Send + 'static,
+    C: Cache<Vec<u8>, Versioned<Bytes>> + Send + Sync + 'static,
 {
     async fn get(&self, request: Request<Key>) -> Result<Response<GetResponse>> {
         let key = request.into_inner().key;
 
-        let mut cache = self.cache.lock().await;
-        match cache.get(&key) {
-            Some(value) => Ok(Response::new(GetResponse {
-                value: Some(Value {
-                    value: value.clone(),
-                }),
-            })),
+        match self.lookup(&key).await {
+            Some(found) => Ok(Response::new(get_response(found))),
             None => Err(Status::not_found("key not found")),
         }
     }
 
     async fn put(&self, request: Request<Entry>) -> Result<Response<PutResponse>> {
-        let Entry { key, value } = request.into_inner();
-        if let (Some(key), Some(value)) = (key, value) {
-            let mut cache = self.cache.lock().await;
-            match cache.put(key.key, value.value) {
-                Ok(()) => Ok(Response::new(PutResponse {})),
-                Err(msg) => Err(Status::internal(msg)),
+        let version = self.put_entry(request.into_inner()).await?;
+        Ok(Response::new(PutResponse { version }))
+    }
+
+    async fn multi_get(
+        &self,
+        request: Request<MultiGetRequest>,
+    ) -> Result<Response<MultiGetResponse>> {
+        let keys = request.into_inner().keys;
+
+        let mut values = Vec::with_capacity(keys.len());
+        for key in keys {
+            values.push(match self.lookup(&key.key).await {
+                Some(found) => get_response(found),
+                None => GetResponse::default(),
+            });
+        }
+        Ok(Response::new(MultiGetResponse {
+            values,
+            failed: vec![],
+        }))
+    }
+
+    async fn multi_put(
+        &self,
+        request: Request<MultiPutRequest>,
+    ) -> Result<Response<MultiPutResponse>> {
+        let entries = request.into_inner().entries;
+        if entries
+            .iter()
+            .any(|entry| entry.key.is_none() || entry.value.is_none())
+        {
+            return Err(Status::invalid_argument("key or value not valid!"));
+        }
+
+        let mut versions = Vec::with_capacity(entries.len());
+        let mut failed = vec![];
+        for (pos, entry) in entries.into_iter().enumerate() {
+            match self.put_entry(entry).await {
+                Ok(version) => versions.push(version),
+                Err(_) => {
+                    versions.push(0);
+                    failed.push(pos as u32);
+                }
             }
+        }
+        Ok(Response::new(MultiPutResponse { versions, failed }))
+    }
+
+    async fn delete(&self, request: Request<Key>) -> Result<Response<DeleteResponse>> {
+        let key = request.into_inner().key;
+
+        match self.delete_entry(key).await {
+            Some(_) => Ok(Response::new(DeleteResponse {})),
+            None => Err(Status::not_found("key not found")),
+        }
+    }
+
+    async fn compare_and_set(
+        &self,
+        request: Request<CompareAndSetRequest>,
+    ) -> Result<Response<CompareAndSetResponse>> {
+        let CompareAndSetRequest { entry, version } = request.into_inner();
+        let entry = entry.ok_or_else(|| Status::invalid_argument("entry not given"))?;
+        if let (Some(key), Some(value)) = (entry.key, entry.value) {
+            let condition = match version {
+                0 => WriteCondition::Absent,
+                version => WriteCondition::Version(version),
+            };
+            let ttl = ttl_from_millis(entry.ttl);
+            let stamp = (entry.version > 0).then_some(entry.version);
+            let (success, version) = match self
+                .store_if(condition, key.key, value.value, 0, ttl, stamp)
+                .await
+            {
+                Ok(version) => (true, version),
+                Err(StoreError::Exists(current) | StoreError::Stale(current)) => (false, current),
+                Err(StoreError::NotFound) => (false, 0),
+                Err(StoreError::TooLarge(msg)) => return Err(Status::internal(msg)),
+            };
+            Ok(Response::new(CompareAndSetResponse { success, version }))
         } else {
             Err(Status::invalid_argument("key or value not valid!"))
         }
     }
 
+    async fn incr(&self, request: Request<CounterRequest>) -> Result<Response<CounterResponse>> {
+        self.count(request.into_inner(), true).await
+    }
+
+    async fn decr(&self, request: Request<CounterRequest>) -> Result<Response<CounterResponse>> {
+        self.count(request.into_inner(), false).await
+    }
+
+    type WatchStream = WatchStream;
+
+    async fn watch(&self, request: Request<WatchRequest>) -> Result<Response<WatchStream>> {
+        let request = request.into_inner();
+        let events = broadcast_stream(self.events.subscribe(), move |event: &WatchEvent| {
+            request.matches(event.key.as_ref().map_or(&[][..], |key| &key.key))
+        });
+        Ok(Response::new(events))
+    }
+
+    async fn publish(&self, request: Request<PublishRequest>) -> Result<Response<PublishResponse>> {
+        let PublishRequest { channel, message } = request.into_inner();
+
+        let mut channels = self.channels.lock().unwrap();
+        let receivers = match channels.get(&channel) {
+            Some(sender) => {
+                let message = ChannelMessage {
+                    channel: channel.clone(),
+                    message,
+                };
+                match sender.send(message) {
+                    Ok(receivers) => receivers as u64,
+                    Err(_) => {
+                        // Every subscriber left
+                        channels.remove(&channel);
+                        0
+                    }
+                }
+            }
+            None => 0,
+        };
+        Ok(Response::new(PublishResponse { receivers }))
+    }
+
+    type SubscribeStream = SubscribeStream;
+
+    async fn subscribe(
+        &self,
+        request: Request<SubscribeRequest>,
+    ) -> Result<Response<SubscribeStream>> {
+        let channel = request.into_inner().channel;
+
+        let receiver = self
+            .channels
+            .lock()
+            .unwrap()
+            .entry(channel.clone())
+            .or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0)
+            .subscribe();
+        Ok(Response::new(Box::pin(Subscription {
+            messages: broadcast_stream(receiver, |_| true),
+            channel,
+            channels: self.channels.clone(),
+        })))
+    }
+
     async fn ping(&self, _: Request<PingRequest>) -> Result<Response<PongResponse>> {
         // TODO: Add conditions regarding the health or other relevant situations
         Ok(Response::new(PongResponse {
@@ -1,9 +1,31 @@
 use crate::{
-    rpc::{self, cache_client::CacheClient, Entry, GetResponse, Key, PutResponse},
-    utils::hash::{xxhash_64, xxhash_64_with_seed},
+    rpc::{
+        self, cache_client::CacheClient, ChannelMessage, CompareAndSetRequest,
+        CompareAndSetResponse, CounterRequest, CounterResponse, DeleteResponse, Entry, GetResponse,
+        Key, MultiGetRequest, MultiGetResponse, MultiPutRequest, MultiPutResponse, PublishRequest,
+        PublishResponse, PutResponse, SubscribeRequest, Value, WatchEvent, WatchRequest,
+    },
+    utils::hash::xxhash_64,
 };
+use futures_util::{
+    future::join_all,
+    stream::{select_all, FuturesUnordered},
+    Stream, StreamExt,
+};
+use router::{Rendezvous, Router};
+use std::collections::HashMap;
+use std::future::Future;
 use std::net::{SocketAddr, ToSocketAddrs};
-use tonic::{transport::Channel, Request, Response, Status};
+use std::pin::Pin;
+use tonic::{transport::Channel, Code, Request, Response, Status, Streaming};
+
+pub mod router;
+
+/// Stream of the changes of the watched entries.
+pub type WatchStream = Pin<Box<dyn Stream<Item = tonic::Result<WatchEvent>> + Send>>;
+
+/// Stream of the messages published to a Pub/Sub channel.
+pub type SubscribeStream = Pin<Box<dyn Stream<Item = tonic::Result<ChannelMessage>> + Send>>;
 
 #[derive(Debug)]
 pub enum Error {
@@ -36,17 +58,51 @@ impl std::fmt::Display for Error {
 
 impl std::error::Error for Error {}
 
+impl From<Error> for Status {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::NoNodesRegistered => {
+                Status::failed_precondition("no cache nodes are connected recently")
+            }
+            _ => Status::unknown("failed due to unknown reason"),
+        }
+    }
+}
+
+/// Times a replicated counter is read and set back before giving up, when
+/// it keeps being changed in between.
+const COUNTER_ATTEMPTS: usize = 8;
+
 pub struct CacheNetwork {
     nodes: Vec<ServerNode>,
+    router: Box<dyn Router>,
+    /// Nodes every entry is stored on
+    replicas: usize,
+    /// Replicas which must store an entry for the write to succeed
+    write_quorum: usize,
+    /// Replicas which must answer for the read to succeed
+    read_quorum: usize,
+    /// Last version stamped on a write, kept above every version read so
+    /// that a write overrides the values it was based on
+    version: u64,
 }
 
 impl CacheNetwork {
     pub fn new() -> Self {
-        CacheNetwork { nodes: vec![] }
+        Self::with_nodes(vec![])
     }
 
     pub fn with_nodes(nodes: Vec<ServerNode>) -> Self {
-        CacheNetwork { nodes }
+        let mut router: Box<dyn Router> = Box::new(Rendezvous::default());
+        router.update(&nodes);
+        CacheNetwork {
+            nodes,
+            router,
+            replicas: 1,
+            write_quorum: 1,
+            read_quorum: 1,
+            version: 0,
+        }
     }
 
     pub fn with_servers(servers: Vec<(&str, usize)>) -> Result<Self, Error> {
@@ -57,12 +113,52 @@ impl CacheNetwork {
         Ok(network)
     }
 
+    /// Routes the keys with the given router instead of rendezvous hashing.
+    pub fn set_router(&mut self, mut router: Box<dyn Router>) {
+        router.update(&self.nodes);
+        self.router = router;
+    }
+
+    /// Stores every entry on `replicas` nodes, where a write succeeds once
+    /// `write_quorum` of them stored it and a read once `read_quorum` of them
+    /// answered. The cluster stamps every write with a version, so that a
+    /// read returns the newest value among the replicas which answered and
+    /// repairs the others with it. Choosing quorums which add up to more than
+    /// the replicas lets every read see the last successful write.
+    ///
+    /// A delete leaves nothing behind, so a replica which missed it can bring
+    /// the value back through a later read.
+    pub fn set_replication(
+        &mut self,
+        replicas: usize,
+        write_quorum: usize,
+        read_quorum: usize,
+    ) -> Result<(), Error> {
+        if replicas == 0 {
+            return Err(Error::Reason(
+                "an entry must be stored on at least one node".to_string(),
+            ));
+        }
+        for quorum in [write_quorum, read_quorum] {
+            if quorum == 0 || quorum > replicas {
+                return Err(Error::Reason(format!(
+                    "a quorum must be between 1 and the {replicas} replicas, got {quorum}"
+                )));
+            }
+        }
+        self.replicas = replicas;
+        self.write_quorum = write_quorum;
+        self.read_quorum = read_quorum;
+        Ok(())
+    }
+
     pub fn add_node(&mut self, node: ServerNode) {
         self.nodes.push(node);
+        self.router.update(&self.nodes);
     }
 
     pub fn add_server(&mut self, addr: &str, weight: usize) -> Result<(), Error> {
-        let node = ServerNode::parse(addr, weight).unwrap();
+        let node = ServerNode::parse(addr, weight)?;
         self.add_node(node);
         Ok(())
     }
@@ -74,59 +170,576 @@ impl CacheNetwork {
                 error_ids.push(node.address());
             }
         }
+        self.router.update(&self.nodes);
         // TODO: Return errors for nodes that couldn't be connected
         Ok(())
     }
 
-    pub fn find_node_with_key(&self, key: &str) -> Result<usize, Error> {
+    pub fn find_node_with_key(&self, key: &[u8]) -> Result<usize, Error> {
         if !self.nodes.is_empty() {
-            let mut node_index = 0;
-            let mut hash: u64 = 0;
-            for (pos, node) in self.nodes.iter().enumerate() {
-                if node.active {
-                    let rendez_hash = xxhash_64_with_seed(key, node.id());
-                    if rendez_hash > hash {
-                        hash = rendez_hash;
-                        node_index = pos;
+            // Without any active node the keys go to the first node, which
+            // fails to serve them
+            return Ok(self.router.route(key).unwrap_or(0));
+        }
+        Err(Error::NoNodesRegistered)
+    }
+
+    /// Returns the indexes of the nodes holding the replicas of the key,
+    /// starting with the node owning it.
+    pub fn find_nodes_with_key(&self, key: &[u8]) -> Result<Vec<usize>, Error> {
+        if self.nodes.is_empty() {
+            return Err(Error::NoNodesRegistered);
+        }
+        let active = self.nodes.iter().filter(|node| node.is_active()).count();
+        let owners = self.router.replicas(key, self.replicas.min(active));
+        if owners.is_empty() {
+            // Same as a single owner, the keys go to the first node
+            return Ok(vec![0]);
+        }
+        Ok(owners)
+    }
+
+    /// Returns a version for a write, newer than every version stamped or
+    /// read by the cluster so far and than `after`, so that the write
+    /// overrides whatever it was based on.
+    fn next_version(&mut self, after: u64) -> u64 {
+        self.version = crate::clock_version()
+            .max(self.version.saturating_add(1))
+            .max(after.saturating_add(1));
+        self.version
+    }
+
+    /// Returns the version to stamp a write with, or 0 when every entry is
+    /// stored on a single node, which then versions its writes itself like
+    /// it does for the counters it counts.
+    fn stamp(&mut self, after: u64) -> u64 {
+        if self.replicas == 1 {
+            0
+        } else {
+            self.next_version(after)
+        }
+    }
+
+    /// Sends a request to each of the given replicas concurrently, and
+    /// returns the answers gathered until `quorum` of them succeeded, or
+    /// until too many of them failed for the quorum to be reached. The
+    /// replicas which have not answered by then still get the request in the
+    /// background, so that a write reaches every replica without waiting for
+    /// the slowest one.
+    async fn replicate<S, F, R, T>(
+        &self,
+        owners: &[usize],
+        quorum: usize,
+        succeeded: S,
+        send: F,
+    ) -> Vec<(usize, tonic::Result<T>)>
+    where
+        S: Fn(&tonic::Result<T>) -> bool,
+        F: Fn(CacheClient<Channel>) -> R,
+        R: Future<Output = tonic::Result<Response<T>>> + Send + 'static,
+        T: Send + 'static,
+    {
+        if owners.len() < quorum {
+            return vec![];
+        }
+        let mut pending = owners
+            .iter()
+            .map(|&node_index| {
+                let node = &self.nodes[node_index];
+                let request = node.client().map(&send).ok_or_else(|| node.not_connected());
+                async move {
+                    let answer = match request {
+                        Ok(request) => request.await.map(Response::into_inner),
+                        Err(status) => Err(status),
+                    };
+                    (node_index, answer)
+                }
+            })
+            .collect::<FuturesUnordered<_>>();
+
+        let mut answers = vec![];
+        let mut successes = 0;
+        while successes < quorum && answers.len() - successes + quorum <= owners.len() {
+            match pending.next().await {
+                Some((node_index, answer)) => {
+                    if succeeded(&answer) {
+                        successes += 1;
                     }
+                    answers.push((node_index, answer));
                 }
+                None => break,
             }
-            return Ok(node_index);
         }
-        Err(Error::NoNodesRegistered)
+        if !pending.is_empty() {
+            tokio::spawn(pending.for_each(|_| async {}));
+        }
+        answers
+    }
+
+    /// Returns the newest of the values held by the replicas of the key, and
+    /// repairs in the background the replicas which hold an older one or none.
+    fn newest(
+        &mut self,
+        key: Vec<u8>,
+        held: Vec<(usize, Option<GetResponse>)>,
+    ) -> Option<GetResponse> {
+        let newest = held
+            .iter()
+            .filter_map(|(_, value)| value.as_ref())
+            .max_by_key(|value| value.version)?
+            .clone();
+        // The writes based on this read must override it
+        self.version = self.version.max(newest.version);
+
+        let entry = Entry {
+            key: Some(Key { key }),
+            value: newest.value.clone(),
+            ttl: newest.ttl,
+            mode: rpc::WriteMode::Set.into(),
+            version: newest.version,
+        };
+        for (node_index, value) in held {
+            if matches!(value, Some(value) if value.version >= newest.version) {
+                continue;
+            }
+            if let Some(mut client) = self.nodes[node_index].client() {
+                let entry = entry.clone();
+                tokio::spawn(async move {
+                    // A replica which fails is repaired by a later read
+                    let _ = client.put(entry).await;
+                });
+            }
+        }
+        Some(newest)
     }
 
+    /// Groups the positions of the keys by the index of every node holding
+    /// a replica of them.
+    fn group_by_node<'a>(
+        &self,
+        keys: impl Iterator<Item = &'a [u8]>,
+    ) -> Result<HashMap<usize, Vec<usize>>, Error> {
+        let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (pos, key) in keys.enumerate() {
+            for node_index in self.find_nodes_with_key(key)? {
+                groups.entry(node_index).or_default().push(pos);
+            }
+        }
+        Ok(groups)
+    }
+
+    /// Gets the values of all the keys, asking every node holding replicas
+    /// of some of them for all of its keys at once and concurrently with the
+    /// other nodes. Each value is the newest held by the replicas of its key,
+    /// which are repaired like for a single get. The keys for which fewer
+    /// nodes than the read quorum answered are reported as failed, without
+    /// throwing away the values of the other keys.
+    pub async fn multi_get(&mut self, keys: Vec<Key>) -> tonic::Result<Response<MultiGetResponse>> {
+        let groups = self.group_by_node(keys.iter().map(|key| key.key.as_slice()))?;
+        let requests = groups.into_iter().map(|(node_index, positions)| {
+            let keys = positions.iter().map(|&pos| keys[pos].clone()).collect();
+            let node = &self.nodes[node_index];
+            let client = node.client().ok_or_else(|| node.not_connected());
+            async move {
+                let response = match client {
+                    Ok(mut client) => client.multi_get(MultiGetRequest { keys }).await,
+                    Err(status) => Err(status),
+                };
+                (node_index, positions, response)
+            }
+        });
+
+        let mut held = vec![vec![]; keys.len()];
+        for (node_index, positions, response) in join_all(requests).await {
+            if let Ok(response) = response {
+                let node_values = response.into_inner().values;
+                for (pos, value) in positions.into_iter().zip(node_values) {
+                    held[pos].push((node_index, value.value.is_some().then_some(value)));
+                }
+            }
+        }
+        let mut values = vec![GetResponse::default(); keys.len()];
+        let mut failed = vec![];
+        for (pos, (key, held)) in keys.into_iter().zip(held).enumerate() {
+            if held.len() < self.read_quorum {
+                failed.push(pos as u32);
+            } else if let Some(value) = self.newest(key.key, held) {
+                values[pos] = value;
+            }
+        }
+        Ok(Response::new(MultiGetResponse { values, failed }))
+    }
+
+    /// Puts all the entries, stamped like a single put, sending
+    /// every node holding replicas of some of them all of its entries at once
+    /// and concurrently with the other nodes. An entry is stored once the
+    /// write quorum of its replicas stored it, and the entries for which
+    /// fewer nodes than the write quorum answered are reported as failed,
+    /// without throwing away the versions of the other entries.
+    pub async fn multi_put(
+        &mut self,
+        mut entries: Vec<Entry>,
+    ) -> tonic::Result<Response<MultiPutResponse>> {
+        let keys = entries
+            .iter()
+            .map(|entry| entry.key.as_ref().map(|key| key.key.as_slice()))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| Status::invalid_argument("key not given"))?;
+        let groups = self.group_by_node(keys.into_iter())?;
+        for entry in &mut entries {
+            entry.version = self.stamp(0);
+        }
+        let requests = groups.into_iter().map(|(node_index, positions)| {
+            let entries = positions.iter().map(|&pos| entries[pos].clone()).collect();
+            let node = &self.nodes[node_index];
+            let client = node.client().ok_or_else(|| node.not_connected());
+            async move {
+                let response = match client {
+                    Ok(mut client) => client.multi_put(MultiPutRequest { entries }).await,
+                    Err(status) => Err(status),
+                };
+                (positions, response)
+            }
+        });
+
+        let mut answered = vec![0; entries.len()];
+        let mut stored = vec![0; entries.len()];
+        let mut versions = vec![0; entries.len()];
+        for (positions, response) in join_all(requests).await {
+            if let Ok(response) = response {
+                let node_versions = response.into_inner().versions;
+                for (pos, version) in positions.into_iter().zip(node_versions) {
+                    answered[pos] += 1;
+                    if version > 0 {
+                        stored[pos] += 1;
+                        versions[pos] = versions[pos].max(version);
+                    }
+                }
+            }
+        }
+        let mut failed = vec![];
+        for (pos, version) in versions.iter_mut().enumerate() {
+            if stored[pos] < self.write_quorum {
+                *version = 0;
+                if answered[pos] < self.write_quorum {
+                    failed.push(pos as u32);
+                }
+            }
+        }
+        Ok(Response::new(MultiPutResponse { versions, failed }))
+    }
+
+    /// Watches the entries on all the nodes, since the watched prefix can
+    /// span all of them, and merges their events into a single stream.
+    pub async fn watch(&mut self, request: WatchRequest) -> tonic::Result<Response<WatchStream>> {
+        let mut streams = vec![];
+        for node in self.nodes.iter_mut().filter(|node| node.is_active()) {
+            streams.push(node.watch(request.clone()).await?.into_inner());
+        }
+        if streams.is_empty() {
+            return Err(Error::NoNodesRegistered.into());
+        }
+        Ok(Response::new(select_all(streams).boxed()))
+    }
+
+    /// Publishes the message on the node owning the channel, which is found
+    /// the same way as the node owning a key.
+    pub async fn publish(
+        &mut self,
+        request: PublishRequest,
+    ) -> tonic::Result<Response<PublishResponse>> {
+        let node_index = self.find_node_with_key(&request.channel)?;
+        self.nodes[node_index].publish(request).await
+    }
+
+    pub async fn subscribe(
+        &mut self,
+        request: SubscribeRequest,
+    ) -> tonic::Result<Response<SubscribeStream>> {
+        let node_index = self.find_node_with_key(&request.channel)?;
+        let messages = self.nodes[node_index].subscribe(request).await?;
+        Ok(messages.map(|messages| messages.boxed()))
+    }
+
+    /// Gets the value from the replicas of the key once the read quorum of
+    /// them answered, and returns the newest of the values they hold. The
+    /// replicas which answered with an older value, or without any, are
+    /// repaired with the newest one in the background.
     pub async fn get_value(&mut self, key: Key) -> tonic::Result<Response<GetResponse>> {
-        match self.find_node_with_key(&key.key) {
-            Ok(node_index) => self.nodes[node_index].get(key).await,
-            Err(err) => match err {
-                Error::NoNodesRegistered => Err(Status::failed_precondition(
-                    "no cache nodes are connected recently",
-                )),
-                _ => Err(Status::unknown("failed due to unknown reason")),
-            },
-        }
-    }
-
-    pub async fn put_entry(&mut self, entry: Entry) -> tonic::Result<Response<PutResponse>> {
-        if let Some(key) = &entry.key {
-            match self.find_node_with_key(&key.key) {
-                Ok(node_index) => self.nodes[node_index].put(entry).await,
-                Err(err) => match err {
-                    Error::NoNodesRegistered => Err(Status::failed_precondition(
-                        "no cache nodes are connected recently",
-                    )),
-                    _ => Err(Status::unknown("failed due to unknown reason")),
+        let owners = self.find_nodes_with_key(&key.key)?;
+        let request = key.clone();
+        let answers = self
+            .replicate(
+                &owners,
+                self.read_quorum,
+                found_or_missing,
+                move |mut client| {
+                    let key = request.clone();
+                    async move { client.get(key).await }
                 },
+            )
+            .await;
+
+        let mut held = vec![];
+        let mut failure = None;
+        for (node_index, answer) in answers {
+            match answer {
+                Ok(value) => held.push((node_index, Some(value))),
+                Err(status) if status.code() == Code::NotFound => held.push((node_index, None)),
+                Err(status) => {
+                    failure.get_or_insert(status);
+                }
             }
+        }
+        if held.len() < self.read_quorum {
+            return Err(quorum_failure(failure, held.len(), self.read_quorum));
+        }
+        self.newest(key.key, held)
+            .map(Response::new)
+            .ok_or_else(|| Status::not_found("key not found"))
+    }
+
+    /// Puts the entry on all the replicas of the key, under a version stamped
+    /// by the cluster when there are several of them, and succeeds once the
+    /// write quorum of them stored it. A replica holding a version at least
+    /// as new refuses the write. The version returned is the one the replicas
+    /// stored.
+    pub async fn put_entry(&mut self, mut entry: Entry) -> tonic::Result<Response<PutResponse>> {
+        let owners = match &entry.key {
+            Some(key) => self.find_nodes_with_key(&key.key)?,
+            None => return Err(Status::invalid_argument("key not given")),
+        };
+        entry.version = self.stamp(0);
+        let answers = self
+            .replicate(
+                &owners,
+                self.write_quorum,
+                Result::is_ok,
+                move |mut client| {
+                    let entry = entry.clone();
+                    async move { client.put(entry).await }
+                },
+            )
+            .await;
+
+        let mut stored = 0;
+        let mut version = 0;
+        for response in answers
+            .iter()
+            .filter_map(|(_, answer)| answer.as_ref().ok())
+        {
+            stored += 1;
+            version = version.max(response.version);
+        }
+        if stored < self.write_quorum {
+            let failure = answers.into_iter().find_map(|(_, answer)| answer.err());
+            return Err(quorum_failure(failure, stored, self.write_quorum));
+        }
+        Ok(Response::new(PutResponse { version }))
+    }
+
+    /// Deletes the key from all of its replicas, and succeeds once the write
+    /// quorum of them answered and any of them held the key.
+    pub async fn delete_entry(&mut self, key: Key) -> tonic::Result<Response<DeleteResponse>> {
+        let owners = self.find_nodes_with_key(&key.key)?;
+        let answers = self
+            .replicate(
+                &owners,
+                self.write_quorum,
+                found_or_missing,
+                move |mut client| {
+                    let key = key.clone();
+                    async move { client.delete(key).await }
+                },
+            )
+            .await;
+
+        let answered = answers
+            .iter()
+            .filter(|(_, answer)| found_or_missing(answer))
+            .count();
+        if answered < self.write_quorum {
+            let failure = answers.into_iter().find_map(|(_, answer)| {
+                answer
+                    .err()
+                    .filter(|status| status.code() != Code::NotFound)
+            });
+            return Err(quorum_failure(failure, answered, self.write_quorum));
+        }
+        if answers.iter().any(|(_, answer)| answer.is_ok()) {
+            Ok(Response::new(DeleteResponse {}))
         } else {
-            Err(Status::invalid_argument("key not given"))
+            Err(Status::not_found("key not found"))
+        }
+    }
+
+    /// Sets the entry, stamped like a put, on the replicas of the key which
+    /// hold it at the expected version, and succeeds once the write quorum of
+    /// them stored it. Otherwise the newest version held by the replicas
+    /// which refused it is returned.
+    pub async fn compare_and_set(
+        &mut self,
+        mut request: CompareAndSetRequest,
+    ) -> tonic::Result<Response<CompareAndSetResponse>> {
+        let owners = match request.entry.as_ref().and_then(|entry| entry.key.as_ref()) {
+            Some(key) => self.find_nodes_with_key(&key.key)?,
+            None => return Err(Status::invalid_argument("key not given")),
+        };
+        let stamp = self.stamp(request.version);
+        if let Some(entry) = &mut request.entry {
+            entry.version = stamp;
+        }
+        let answers = self
+            .replicate(
+                &owners,
+                self.write_quorum,
+                |answer: &tonic::Result<CompareAndSetResponse>| {
+                    matches!(answer, Ok(response) if response.success)
+                },
+                move |mut client| {
+                    let request = request.clone();
+                    async move { client.compare_and_set(request).await }
+                },
+            )
+            .await;
+
+        let mut stored = 0;
+        let mut version = 0;
+        let mut refused = None;
+        let mut failure = None;
+        for (_, answer) in answers {
+            match answer {
+                Ok(response) if response.success => {
+                    stored += 1;
+                    version = version.max(response.version);
+                }
+                Ok(response) => refused = refused.max(Some(response.version)),
+                Err(status) => {
+                    failure.get_or_insert(status);
+                }
+            }
+        }
+        if stored >= self.write_quorum {
+            return Ok(Response::new(CompareAndSetResponse {
+                success: true,
+                version,
+            }));
+        }
+        match refused {
+            Some(current) => Ok(Response::new(CompareAndSetResponse {
+                success: false,
+                version: current,
+            })),
+            None => Err(quorum_failure(failure, stored, self.write_quorum)),
+        }
+    }
+
+    /// Adds the delta to the counter, or subtracts it if `increment` is
+    /// false. A counter stored on a single node is counted by the node
+    /// itself, while a replicated one is read from its replicas and set back
+    /// on them with a compare-and-set, again if it changed in between.
+    async fn count(
+        &mut self,
+        request: CounterRequest,
+        increment: bool,
+    ) -> tonic::Result<Response<CounterResponse>> {
+        let key = match &request.key {
+            Some(key) => key.clone(),
+            None => return Err(Status::invalid_argument("key not given")),
+        };
+        if self.replicas == 1 {
+            let node_index = self.find_node_with_key(&key.key)?;
+            let node = &mut self.nodes[node_index];
+            return if increment {
+                node.incr(request).await
+            } else {
+                node.decr(request).await
+            };
+        }
+        let delta =
+            i64::try_from(request.delta).map_err(|_| Status::out_of_range("delta is too large"))?;
+
+        for _ in 0..COUNTER_ATTEMPTS {
+            let (current, version, ttl) = match self.get_value(key.clone()).await {
+                Ok(response) => {
+                    let response = response.into_inner();
+                    let current = response
+                        .value
+                        .as_ref()
+                        .and_then(|value| std::str::from_utf8(&value.value).ok())
+                        .and_then(|value| value.parse::<i64>().ok())
+                        .ok_or_else(|| Status::failed_precondition("value is not an integer"))?;
+                    (current, response.version, response.ttl)
+                }
+                Err(status) if status.code() == Code::NotFound => (request.initial, 0, request.ttl),
+                Err(status) => return Err(status),
+            };
+            let value = if increment {
+                current.checked_add(delta)
+            } else {
+                current.checked_sub(delta)
+            }
+            .ok_or_else(|| Status::out_of_range("counter would overflow"))?;
+
+            let entry = Entry {
+                key: Some(key.clone()),
+                value: Some(Value {
+                    value: value.to_string().into(),
+                }),
+                ttl,
+                mode: rpc::WriteMode::Set.into(),
+                version: 0,
+            };
+            let request = CompareAndSetRequest {
+                entry: Some(entry),
+                version,
+            };
+            let response = self.compare_and_set(request).await?.into_inner();
+            if response.success {
+                return Ok(Response::new(CounterResponse {
+                    value,
+                    version: response.version,
+                }));
+            }
         }
+        Err(Status::aborted("counter kept changing while being counted"))
+    }
+
+    pub async fn incr(
+        &mut self,
+        request: CounterRequest,
+    ) -> tonic::Result<Response<CounterResponse>> {
+        self.count(request, true).await
     }
+
+    pub async fn decr(
+        &mut self,
+        request: CounterRequest,
+    ) -> tonic::Result<Response<CounterResponse>> {
+        self.count(request, false).await
+    }
+}
+
+/// Whether a replica answered a request on a key, holding the key or not.
+fn found_or_missing<T>(answer: &tonic::Result<T>) -> bool {
+    !matches!(answer, Err(status) if status.code() != Code::NotFound)
+}
+
+/// Returns why a request did not reach its quorum, which is the failure of
+/// a replica if any failed.
+fn quorum_failure(failure: Option<Status>, successes: usize, quorum: usize) -> Status {
+    failure.unwrap_or_else(|| {
+        Status::unavailable(format!(
+            "only {successes} replicas answered out of the {quorum} needed"
+        ))
+    })
 }
 
 pub struct ServerNode {
     id: u64,
+    /// The address the node was configured with, before the host was
+    /// resolved
+    name: String,
     host: String,
     port: u16,
     weight: usize,
@@ -135,15 +748,20 @@ pub struct ServerNode {
 }
 
 impl ServerNode {
-    fn address_from(mut host: String, port: u16) -> String {
-        host.push_str(&port.to_string());
-        host
+    fn address_from(host: String, port: u16) -> String {
+        // IPv6 addresses are bracketed to tell them apart from the port
+        if host.contains(':') {
+            format!("[{host}]:{port}")
+        } else {
+            format!("{host}:{port}")
+        }
     }
 
     pub fn new(host: String, port: u16, weight: usize) -> Self {
         let address = Self::address_from(host.clone(), port);
         ServerNode {
             id: xxhash_64(address.as_str()),
+            name: address,
             host,
             port,
             weight,
@@ -163,6 +781,10 @@ impl ServerNode {
                 }
                 None => Err(Error::NotValidAddress),
             }
+            .map(|node| ServerNode {
+                name: addr.to_string(),
+                ..node
+            })
         } else {
             Err(Error::NotValidAddress)
         }
@@ -180,6 +802,12 @@ impl ServerNode {
         self.port
     }
 
+    /// Returns the address the node was configured with, such as
+    /// `cache1:11211`, where [`ServerNode::address`] has the host resolved.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     pub fn address(&self) -> String {
         Self::address_from(self.host(), self.port)
     }
@@ -192,9 +820,20 @@ impl ServerNode {
         self.active
     }
 
+    /// Returns the connection to the node, which is shared by its clones so
+    /// that a request can outlive the borrow of the node.
+    fn client(&self) -> Option<CacheClient<Channel>> {
+        self.client.clone()
+    }
+
+    fn not_connected(&self) -> Status {
+        Status::failed_precondition(format!("node {} couldn't not be connected", self.address()))
+    }
+
     pub async fn connect(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         if self.client.is_none() {
-            self.client = Some(CacheClient::connect(self.address()).await?);
+            let endpoint = format!("http://{}", self.address());
+            self.client = Some(CacheClient::connect(endpoint).await?);
             self.active = true;
         }
         Ok(())
@@ -221,4 +860,127 @@ impl ServerNode {
             )))
         }
     }
+
+    pub async fn delete(&mut self, key: Key) -> tonic::Result<Response<DeleteResponse>> {
+        if let Some(conn) = &mut self.client {
+            conn.delete(Request::new(key)).await
+        } else {
+            Err(Status::failed_precondition(format!(
+                "node {} couldn't not be connected",
+                self.address()
+            )))
+        }
+    }
+
+    pub async fn compare_and_set(
+        &mut self,
+        request: CompareAndSetRequest,
+    ) -> tonic::Result<Response<CompareAndSetResponse>> {
+        if let Some(conn) = &mut self.client {
+            conn.compare_and_set(Request::new(request)).await
+        } else {
+            Err(Status::failed_precondition(format!(
+                "node {} couldn't not be connected",
+                self.address()
+            )))
+        }
+    }
+
+    pub async fn incr(
+        &mut self,
+        request: CounterRequest,
+    ) -> tonic::Result<Response<CounterResponse>> {
+        if let Some(conn) = &mut self.client {
+            conn.incr(Request::new(request)).await
+        } else {
+            Err(Status::failed_precondition(format!(
+                "node {} couldn't not be connected",
+                self.address()
+            )))
+        }
+    }
+
+    pub async fn decr(
+        &mut self,
+        request: CounterRequest,
+    ) -> tonic::Result<Response<CounterResponse>> {
+        if let Some(conn) = &mut self.client {
+            conn.decr(Request::new(request)).await
+        } else {
+            Err(Status::failed_precondition(format!(
+                "node {} couldn't not be connected",
+                self.address()
+            )))
+        }
+    }
+
+    pub async fn multi_get(
+        &mut self,
+        request: MultiGetRequest,
+    ) -> tonic::Result<Response<MultiGetResponse>> {
+        if let Some(conn) = &mut self.client {
+            conn.multi_get(Request::new(request)).await
+        } else {
+            Err(Status::failed_precondition(format!(
+                "node {} couldn't not be connected",
+                self.address()
+            )))
+        }
+    }
+
+    pub async fn multi_put(
+        &mut self,
+        request: MultiPutRequest,
+    ) -> tonic::Result<Response<MultiPutResponse>> {
+        if let Some(conn) = &mut self.client {
+            conn.multi_put(Request::new(request)).await
+        } else {
+            Err(Status::failed_precondition(format!(
+                "node {} couldn't not be connected",
+                self.address()
+            )))
+        }
+    }
+
+    pub async fn watch(
+        &mut self,
+        request: WatchRequest,
+    ) -> tonic::Result<Response<Streaming<WatchEvent>>> {
+        if let Some(conn) = &mut self.client {
+            conn.watch(Request::new(request)).await
+        } else {
+            Err(Status::failed_precondition(format!(
+                "node {} couldn't not be connected",
+                self.address()
+            )))
+        }
+    }
+
+    pub async fn publish(
+        &mut self,
+        request: PublishRequest,
+    ) -> tonic::Result<Response<PublishResponse>> {
+        if let Some(conn) = &mut self.client {
+            conn.publish(Request::new(request)).await
+        } else {
+            Err(Status::failed_precondition(format!(
+                "node {} couldn't not be connected",
+                self.address()
+            )))
+        }
+    }
+
+    pub async fn subscribe(
+        &mut self,
+        request: SubscribeRequest,
+    ) -> tonic::Result<Response<Streaming<ChannelMessage>>> {
+        if let Some(conn) = &mut self.client {
+            conn.subscribe(Request::new(request)).await
+        } else {
+            Err(Status::failed_precondition(format!(
+                "node {} couldn't not be connected",
+                self.address()
+            )))
+        }
+    }
 }
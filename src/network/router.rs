@@ -0,0 +1,576 @@
+use super::ServerNode;
+use crate::utils::hash::{xxhash_64, xxhash_64_with_seed};
+
+/// Decides which node owns each key.
+pub trait Router: Send + Sync {
+    /// Rebuilds the routing out of the nodes, whenever a node is added or
+    /// changes its state.
+    fn update(&mut self, nodes: &[ServerNode]);
+
+    /// Returns the index of the active node owning the key, or `None` if no
+    /// node is active.
+    fn route(&self, key: &[u8]) -> Option<usize>;
+
+    /// Returns the indexes of up to `count` distinct active nodes to hold
+    /// the replicas of the key, starting with the node owning it.
+    fn replicas(&self, key: &[u8], count: usize) -> Vec<usize>;
+}
+
+/// Collects the first `count` distinct nodes out of the walk over the nodes
+/// of a ring or table.
+fn distinct_nodes(walk: impl Iterator<Item = usize>, count: usize) -> Vec<usize> {
+    let mut nodes = Vec::with_capacity(count);
+    for index in walk {
+        if nodes.len() == count {
+            break;
+        }
+        if !nodes.contains(&index) {
+            nodes.push(index);
+        }
+    }
+    nodes
+}
+
+/// Weighted rendezvous hashing, which scores every node for each key and
+/// routes the key to the node with the highest score. Lookups are linear in
+/// the number of nodes, while the keys of a node which leaves are the only
+/// ones to move.
+#[derive(Default)]
+pub struct Rendezvous {
+    /// Index, id and weight of the active nodes
+    nodes: Vec<(usize, u64, usize)>,
+}
+
+/// Score of a node for the key. Scoring `-weight / ln(h)` for a hash `h`
+/// uniform in (0, 1) gives each node a share of the keys proportional to its
+/// weight.
+fn rendezvous_score(key: &[u8], id: u64, weight: usize) -> f64 {
+    let hash = xxhash_64_with_seed(key, id);
+    // The 53 high bits fit in the mantissa, and the half keeps the logarithm
+    // away from 0
+    let unit = ((hash >> 11) as f64 + 0.5) / (1u64 << 53) as f64;
+    -(weight as f64) / unit.ln()
+}
+
+impl Router for Rendezvous {
+    fn update(&mut self, nodes: &[ServerNode]) {
+        self.nodes = nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.is_active())
+            .map(|(index, node)| (index, node.id(), node.weight()))
+            .collect();
+    }
+
+    fn route(&self, key: &[u8]) -> Option<usize> {
+        let mut owner = None;
+        let mut score = 0.0;
+        for &(index, id, weight) in &self.nodes {
+            let node_score = rendezvous_score(key, id, weight);
+            if owner.is_none() || node_score > score {
+                score = node_score;
+                owner = Some(index);
+            }
+        }
+        owner
+    }
+
+    fn replicas(&self, key: &[u8], count: usize) -> Vec<usize> {
+        // The replicas go to the nodes with the highest scores after the
+        // owner, which keeps them in place as other nodes come and go
+        let mut scores: Vec<_> = self
+            .nodes
+            .iter()
+            .map(|&(index, id, weight)| (rendezvous_score(key, id, weight), index))
+            .collect();
+        scores.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scores
+            .into_iter()
+            .take(count)
+            .map(|(_, index)| index)
+            .collect()
+    }
+}
+
+/// Points a node of weight 1 gets on the ring by default, same as ketama.
+pub const DEFAULT_VIRTUAL_NODES: usize = 160;
+
+/// Consistent hash ring laid out like ketama, so that the keys are owned by
+/// the same nodes as with the memcached clients using it. Every node is
+/// placed on the ring at several points, and a key is owned by the node of
+/// the first point from its hash on, which takes a binary search.
+pub struct Ketama {
+    virtual_nodes: usize,
+    /// Points of the ring along with the index of their node, by position
+    ring: Vec<(u32, usize)>,
+}
+
+impl Ketama {
+    /// Creates a ring placing every node at about `virtual_nodes` points
+    /// times its share of the total weight.
+    pub fn new(virtual_nodes: usize) -> Self {
+        Ketama {
+            virtual_nodes: virtual_nodes.max(4),
+            ring: vec![],
+        }
+    }
+}
+
+impl Ketama {
+    /// Returns the position of the first point of the ring from the hash of
+    /// the key on, or `0` if the ring is empty.
+    fn position(&self, key: &[u8]) -> usize {
+        let digest = md5::compute(key);
+        let hash = u32::from_le_bytes([digest[0], digest[1], digest[2], digest[3]]);
+        let pos = self.ring.partition_point(|&(point, _)| point < hash);
+        // Past the last point the ring wraps around to the first one
+        if pos == self.ring.len() {
+            0
+        } else {
+            pos
+        }
+    }
+}
+
+impl Default for Ketama {
+    fn default() -> Self {
+        Self::new(DEFAULT_VIRTUAL_NODES)
+    }
+}
+
+impl Router for Ketama {
+    fn update(&mut self, nodes: &[ServerNode]) {
+        let active: Vec<_> = nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.is_active())
+            .collect();
+        let total_weight: usize = active.iter().map(|(_, node)| node.weight()).sum();
+
+        self.ring.clear();
+        if total_weight == 0 {
+            return;
+        }
+        for &(index, node) in &active {
+            // Every digest gives 4 points, and the nodes get a number of
+            // digests proportional to their weight, as in ketama
+            let digests = node.weight() * self.virtual_nodes / 4 * active.len() / total_weight;
+            // Like ketama, the points come from the configured address rather
+            // than the resolved one, such as `cache1:11211`
+            let name = node.name();
+            for i in 0..digests {
+                let digest = md5::compute(format!("{name}-{i}"));
+                for point in digest.chunks_exact(4) {
+                    let point = u32::from_le_bytes([point[0], point[1], point[2], point[3]]);
+                    self.ring.push((point, index));
+                }
+            }
+        }
+        self.ring.sort_by_key(|&(point, _)| point);
+    }
+
+    fn route(&self, key: &[u8]) -> Option<usize> {
+        self.ring.get(self.position(key)).map(|&(_, index)| index)
+    }
+
+    fn replicas(&self, key: &[u8], count: usize) -> Vec<usize> {
+        // The replicas go to the next nodes clockwise from the owner
+        let pos = self.position(key);
+        let walk = self.ring[pos..].iter().chain(&self.ring[..pos]);
+        distinct_nodes(walk.map(|&(_, index)| index), count)
+    }
+}
+
+/// Jump consistent hash, which maps the hash of a key straight to a bucket
+/// without any table, and moves the fewest keys when buckets are added or
+/// removed at the end. Every active node takes as many buckets as its
+/// weight, in the order the nodes were added.
+#[derive(Default)]
+pub struct JumpHash {
+    /// Index of the node of each bucket
+    buckets: Vec<usize>,
+}
+
+/// Returns the bucket out of `buckets` the hash falls in, as described by
+/// Lamping and Veach.
+fn jump_consistent_hash(mut hash: u64, buckets: usize) -> usize {
+    let mut bucket = 0;
+    let mut next = 0;
+    while next < buckets {
+        bucket = next;
+        hash = hash.wrapping_mul(2862933555777941757).wrapping_add(1);
+        next = ((bucket + 1) as f64 * ((1u64 << 31) as f64 / ((hash >> 33) + 1) as f64)) as usize;
+    }
+    bucket
+}
+
+impl Router for JumpHash {
+    fn update(&mut self, nodes: &[ServerNode]) {
+        self.buckets = nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.is_active())
+            .flat_map(|(index, node)| std::iter::repeat(index).take(node.weight()))
+            .collect();
+    }
+
+    fn route(&self, key: &[u8]) -> Option<usize> {
+        if self.buckets.is_empty() {
+            return None;
+        }
+        let bucket = jump_consistent_hash(xxhash_64(key), self.buckets.len());
+        Some(self.buckets[bucket])
+    }
+
+    fn replicas(&self, key: &[u8], count: usize) -> Vec<usize> {
+        if self.buckets.is_empty() {
+            return vec![];
+        }
+        // The replicas go to the nodes of the buckets following the one of
+        // the key
+        let bucket = jump_consistent_hash(xxhash_64(key), self.buckets.len());
+        let walk = self.buckets[bucket..].iter().chain(&self.buckets[..bucket]);
+        distinct_nodes(walk.copied(), count)
+    }
+}
+
+/// Size of the Maglev lookup table by default, a prime much larger than the
+/// number of nodes.
+pub const DEFAULT_TABLE_SIZE: usize = 65537;
+
+/// Maglev hashing, which fills a lookup table by letting the nodes take
+/// turns claiming the slots in the order of their own permutation. Lookups
+/// take a single hash and index, and every node owns an even share of the
+/// slots weighted by its weight.
+pub struct Maglev {
+    table_size: usize,
+    /// Index of the node owning each slot
+    table: Vec<usize>,
+}
+
+impl Maglev {
+    /// Creates a router with a lookup table of the given size.
+    ///
+    /// Panics if the size is not a prime, as the permutations of the nodes
+    /// would then not cover the whole table.
+    pub fn new(table_size: usize) -> Self {
+        let is_prime = table_size > 1
+            && (2..)
+                .take_while(|divisor| divisor * divisor <= table_size)
+                .all(|divisor| table_size % divisor != 0);
+        assert!(is_prime, "the size of the lookup table must be a prime");
+        Maglev {
+            table_size,
+            table: vec![],
+        }
+    }
+}
+
+impl Default for Maglev {
+    fn default() -> Self {
+        Self::new(DEFAULT_TABLE_SIZE)
+    }
+}
+
+impl Router for Maglev {
+    fn update(&mut self, nodes: &[ServerNode]) {
+        let size = self.table_size;
+        // Offset, skip and weight of the permutation of every active node
+        let mut permutations: Vec<_> = nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.is_active() && node.weight() > 0)
+            .map(|(index, node)| {
+                let address = node.address();
+                let offset = xxhash_64_with_seed(&address, 0) as usize % size;
+                let skip = xxhash_64_with_seed(&address, 1) as usize % (size - 1) + 1;
+                (index, offset, skip, node.weight(), 0)
+            })
+            .collect();
+
+        self.table.clear();
+        if permutations.is_empty() {
+            return;
+        }
+        let mut table = vec![usize::MAX; size];
+        let mut filled = 0;
+        'fill: loop {
+            for (index, offset, skip, weight, next) in &mut permutations {
+                for _ in 0..*weight {
+                    let mut slot = (*offset + *next * *skip) % size;
+                    while table[slot] != usize::MAX {
+                        *next += 1;
+                        slot = (*offset + *next * *skip) % size;
+                    }
+                    table[slot] = *index;
+                    *next += 1;
+                    filled += 1;
+                    if filled == size {
+                        break 'fill;
+                    }
+                }
+            }
+        }
+        self.table = table;
+    }
+
+    fn route(&self, key: &[u8]) -> Option<usize> {
+        if self.table.is_empty() {
+            return None;
+        }
+        Some(self.table[xxhash_64(key) as usize % self.table.len()])
+    }
+
+    fn replicas(&self, key: &[u8], count: usize) -> Vec<usize> {
+        if self.table.is_empty() {
+            return vec![];
+        }
+        // The replicas go to the nodes of the slots following the one of the
+        // key
+        let slot = xxhash_64(key) as usize % self.table.len();
+        let walk = self.table[slot..].iter().chain(&self.table[..slot]);
+        distinct_nodes(walk.copied(), count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nodes(weights: &[usize]) -> Vec<ServerNode> {
+        weights
+            .iter()
+            .enumerate()
+            .map(|(i, &weight)| {
+                let mut node = ServerNode::new(format!("10.0.1.{}", i + 1), 11211, weight);
+                node.active = true;
+                node
+            })
+            .collect()
+    }
+
+    fn keys() -> impl Iterator<Item = String> {
+        (0..20_000).map(|i| format!("key-{i}"))
+    }
+
+    /// Number of keys each node owns.
+    fn spread(router: &dyn Router, nodes: usize) -> Vec<usize> {
+        let mut owned = vec![0; nodes];
+        for key in keys() {
+            owned[router.route(key.as_bytes()).unwrap()] += 1;
+        }
+        owned
+    }
+
+    /// Asserts that the nodes own shares of the keys within 10% of their
+    /// weights.
+    fn assert_even(owned: &[usize], weights: &[usize]) {
+        let total_weight: usize = weights.iter().sum();
+        let total: usize = owned.iter().sum();
+        for (&owned, &weight) in owned.iter().zip(weights) {
+            let expected = (total * weight / total_weight) as f64;
+            assert!(
+                (owned as f64 - expected).abs() < expected * 0.1,
+                "{owned} keys instead of about {expected}"
+            );
+        }
+    }
+
+    /// Asserts that adding a node only moves keys to it, but for a fraction
+    /// `slack` of the keys.
+    fn assert_moves_to_the_new_node(mut router: Box<dyn Router>, slack: f64) {
+        let mut nodes = nodes(&[1; 4]);
+        router.update(&nodes);
+        let before: Vec<_> = keys().map(|key| router.route(key.as_bytes())).collect();
+        nodes.extend(self::nodes(&[1; 5]).pop());
+        router.update(&nodes);
+        let (mut moved, mut elsewhere) = (0, 0);
+        for (key, before) in keys().zip(before) {
+            match router.route(key.as_bytes()) {
+                after if after == before => {}
+                Some(4) => moved += 1,
+                _ => elsewhere += 1,
+            }
+        }
+        let count = keys().count() as f64;
+        assert!(
+            (moved as f64 / count - 0.2).abs() < 0.03,
+            "{moved} keys moved"
+        );
+        assert!(
+            elsewhere as f64 <= count * slack,
+            "{elsewhere} keys moved elsewhere"
+        );
+    }
+
+    fn routers() -> Vec<Box<dyn Router>> {
+        vec![
+            Box::new(Rendezvous::default()),
+            Box::new(Ketama::default()),
+            Box::new(JumpHash::default()),
+            Box::new(Maglev::default()),
+        ]
+    }
+
+    #[test]
+    fn inactive_nodes_are_skipped() {
+        for mut router in routers() {
+            let mut nodes = nodes(&[1; 3]);
+            nodes[1].active = false;
+            router.update(&nodes);
+            for key in keys().take(1000) {
+                assert_ne!(router.route(key.as_bytes()), Some(1));
+            }
+            router.update(&[]);
+            assert_eq!(router.route(b"k"), None);
+        }
+    }
+
+    #[test]
+    fn replicas_are_distinct_active_nodes() {
+        for mut router in routers() {
+            let mut nodes = nodes(&[1; 4]);
+            nodes[1].active = false;
+            router.update(&nodes);
+            for key in keys().take(1000) {
+                let replicas = router.replicas(key.as_bytes(), 4);
+                assert_eq!(replicas.len(), 3);
+                assert_eq!(Some(replicas[0]), router.route(key.as_bytes()));
+                assert!(!replicas.contains(&1));
+                assert!(replicas[1] != replicas[0] && replicas[2] != replicas[0]);
+                assert_ne!(replicas[1], replicas[2]);
+                assert_eq!(router.replicas(key.as_bytes(), 2), replicas[..2]);
+            }
+            router.update(&[]);
+            assert!(router.replicas(b"k", 2).is_empty());
+        }
+    }
+
+    /// The continuum of libketama, as laid out by `ketama_create_continuum`
+    /// and searched by `ketama_get_server`.
+    fn libketama_server(servers: &[ServerNode], key: &str) -> usize {
+        let md5_point = |digest: &[u8], h: usize| {
+            ((digest[3 + h * 4] as u32) << 24)
+                | ((digest[2 + h * 4] as u32) << 16)
+                | ((digest[1 + h * 4] as u32) << 8)
+                | digest[h * 4] as u32
+        };
+        let total_weight: usize = servers.iter().map(|server| server.weight()).sum();
+        let mut continuum = vec![];
+        for (index, server) in servers.iter().enumerate() {
+            let pct = server.weight() as f32 / total_weight as f32;
+            let ks = (pct as f64 * 40.0 * servers.len() as f64).floor() as usize;
+            for k in 0..ks {
+                let digest = md5::compute(format!("{}-{k}", server.name()));
+                for h in 0..4 {
+                    continuum.push((md5_point(&digest[..], h), index));
+                }
+            }
+        }
+        continuum.sort_by_key(|&(point, _)| point);
+        let hash = md5_point(&md5::compute(key)[..], 0);
+        continuum
+            .iter()
+            .find(|&&(point, _)| point >= hash)
+            .unwrap_or(&continuum[0])
+            .1
+    }
+
+    #[test]
+    fn ketama_agrees_with_libketama() {
+        // md5("a") starts with 0c c1 75 b9, read as a little endian word
+        let mut ring = Ketama {
+            virtual_nodes: DEFAULT_VIRTUAL_NODES,
+            ring: vec![(0xb975c10b, 0), (0xb975c10c, 1), (0xb975c10d, 2)],
+        };
+        assert_eq!(ring.route(b"a"), Some(1));
+        ring.ring.remove(1);
+        assert_eq!(ring.route(b"a"), Some(2));
+        ring.ring.pop();
+        assert_eq!(ring.route(b"a"), Some(0));
+
+        for weights in [&[1, 1, 1, 1][..], &[1, 2, 3], &[5]] {
+            let nodes = nodes(weights);
+            let mut ring = Ketama::default();
+            ring.update(&nodes);
+            let points = 160 * weights.len();
+            assert!(ring.ring.len() <= points && ring.ring.len() + 4 * weights.len() > points);
+            for key in keys().take(5000) {
+                assert_eq!(
+                    ring.route(key.as_bytes()),
+                    Some(libketama_server(&nodes, &key)),
+                    "{key}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn ketama_places_the_nodes_by_their_configured_address() {
+        let mut node = ServerNode::parse("localhost:11211", 1).unwrap();
+        node.active = true;
+        assert_eq!(node.name(), "localhost:11211");
+        let mut ring = Ketama::default();
+        ring.update(&[node]);
+        let digest = md5::compute("localhost:11211-0");
+        let point = u32::from_le_bytes([digest[0], digest[1], digest[2], digest[3]]);
+        assert!(ring.ring.contains(&(point, 0)));
+    }
+
+    #[test]
+    fn ketama_spreads_the_keys() {
+        let mut ring = Ketama::default();
+        ring.update(&nodes(&[1; 4]));
+        // The ring is coarser than the other routers
+        let owned = spread(&ring, 4);
+        assert!(
+            owned.iter().all(|&owned| owned > 3500 && owned < 6500),
+            "{owned:?}"
+        );
+        assert_moves_to_the_new_node(Box::new(Ketama::default()), 0.0);
+    }
+
+    #[test]
+    fn rendezvous_spreads_the_keys_by_weight() {
+        let weights = [1, 2, 1, 4];
+        let mut router = Rendezvous::default();
+        router.update(&nodes(&weights));
+        assert_even(&spread(&router, 4), &weights);
+        assert_moves_to_the_new_node(Box::new(Rendezvous::default()), 0.0);
+    }
+
+    #[test]
+    fn jump_hash_spreads_the_keys_by_weight() {
+        let weights = [1, 2, 1, 4];
+        let mut router = JumpHash::default();
+        router.update(&nodes(&weights));
+        assert_even(&spread(&router, 4), &weights);
+        assert_eq!(jump_consistent_hash(42, 1), 0);
+        assert_moves_to_the_new_node(Box::new(JumpHash::default()), 0.0);
+    }
+
+    #[test]
+    fn maglev_spreads_the_keys_by_weight() {
+        let weights = [1, 2, 1, 4];
+        let mut router = Maglev::default();
+        router.update(&nodes(&weights));
+        let mut slots = [0usize; 4];
+        for &index in &router.table {
+            slots[index] += 1;
+        }
+        // Every node claims as many slots per turn as its weight
+        let turns = DEFAULT_TABLE_SIZE / 8;
+        for (&slots, &weight) in slots.iter().zip(&weights) {
+            assert!(slots.abs_diff(turns * weight) <= weight, "{slots} slots");
+        }
+        assert_even(&spread(&router, 4), &weights);
+        // Maglev trades a little disruption for its even spread
+        assert_moves_to_the_new_node(Box::new(Maglev::default()), 0.02);
+    }
+
+    #[test]
+    #[should_panic(expected = "must be a prime")]
+    fn maglev_table_size_is_a_prime() {
+        Maglev::new(65536);
+    }
+}
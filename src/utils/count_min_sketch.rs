@@ -0,0 +1,117 @@
+use super::hash::xxhash_64_with_seed;
+
+/// Number of rows (independent hash functions) of the sketch.
+const DEPTH: usize = 4;
+
+/// Largest value a counter can reach, mirroring 4-bit counters.
+const MAX_COUNT: u8 = 15;
+
+/// Represents a count-min sketch that estimates the access frequency of keys
+/// within a bounded amount of memory.
+///
+/// Once the number of recorded increments reaches the sample size, all the
+/// counters are halved so that the keys which were popular long ago slowly
+/// lose their frequency.
+pub struct CountMinSketch {
+    table: Vec<u8>,
+    width_mask: usize,
+    additions: usize,
+    sample_size: usize,
+}
+
+impl CountMinSketch {
+    /// Creates a new [`CountMinSketch`] sized for a cache of the given `capacity`.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let width = capacity.max(16).next_power_of_two();
+        CountMinSketch {
+            table: vec![0; DEPTH * width],
+            width_mask: width - 1,
+            additions: 0,
+            sample_size: 10 * width,
+        }
+    }
+
+    fn slot<T: AsRef<[u8]> + ?Sized>(&self, key: &T, row: usize) -> usize {
+        let hash = xxhash_64_with_seed(key, row as u64) as usize;
+        row * (self.width_mask + 1) + (hash & self.width_mask)
+    }
+
+    /// Returns the estimated access frequency of the given `key`.
+    pub fn frequency<T: AsRef<[u8]> + ?Sized>(&self, key: &T) -> u8 {
+        (0..DEPTH)
+            .map(|row| self.table[self.slot(key, row)])
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// Records an access of the given `key`.
+    pub fn increment<T: AsRef<[u8]> + ?Sized>(&mut self, key: &T) {
+        let mut incremented = false;
+        for row in 0..DEPTH {
+            let slot = self.slot(key, row);
+            if self.table[slot] < MAX_COUNT {
+                self.table[slot] += 1;
+                incremented = true;
+            }
+        }
+
+        if incremented {
+            self.additions += 1;
+            if self.additions >= self.sample_size {
+                self.reset();
+            }
+        }
+    }
+
+    /// Ages the sketch by halving all the counters.
+    fn reset(&mut self) {
+        for counter in self.table.iter_mut() {
+            *counter >>= 1;
+        }
+        self.additions /= 2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_and_saturates() {
+        let mut sketch = CountMinSketch::with_capacity(16);
+        assert_eq!(sketch.frequency("a"), 0);
+        for _ in 0..5 {
+            sketch.increment("a");
+        }
+        assert_eq!(sketch.frequency("a"), 5);
+        for _ in 0..20 {
+            sketch.increment("a");
+        }
+        assert_eq!(sketch.frequency("a"), MAX_COUNT);
+        // Saturated increments do not count towards the aging
+        assert_eq!(sketch.additions, 15);
+    }
+
+    #[test]
+    fn never_underestimates() {
+        let mut sketch = CountMinSketch::with_capacity(64);
+        for i in 0..200u32 {
+            for _ in 0..i % 4 {
+                sketch.increment(&i.to_be_bytes());
+            }
+        }
+        assert!((0..200u32).all(|i| sketch.frequency(&i.to_be_bytes()) as u32 >= i % 4));
+    }
+
+    #[test]
+    fn ages_once_the_sample_is_full() {
+        let mut sketch = CountMinSketch::with_capacity(16);
+        for _ in 0..15 {
+            sketch.increment("a");
+        }
+        sketch.additions = sketch.sample_size - 1;
+        sketch.increment("b");
+        assert_eq!(sketch.frequency("a"), 7);
+        assert_eq!(sketch.additions, sketch.sample_size / 2);
+    }
+}
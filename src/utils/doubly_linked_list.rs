@@ -31,6 +31,26 @@ impl<T> DoublyLinkedList<T> {
         }
     }
 
+    /// Returns the number of elements in the linked list.
+    pub fn len(&self) -> usize {
+        self.arena.len()
+    }
+
+    /// Returns true if the linked list has no elements.
+    pub fn is_empty(&self) -> bool {
+        self.head.is_none()
+    }
+
+    /// Returns true if no more elements can be added without growing.
+    pub fn is_full(&self) -> bool {
+        self.arena.is_full()
+    }
+
+    /// Grows the linked list to hold `additional` more elements.
+    pub fn reserve(&mut self, additional: usize) {
+        self.arena.reserve(additional);
+    }
+
     /// Pushes the new element to the end of the linked list.
     pub fn push(&mut self, elem: T) -> Result<usize, &'static str> {
         match self.arena.push(Node {
@@ -73,34 +93,62 @@ impl<T> DoublyLinkedList<T> {
         }
     }
 
+    /// Puts the new element at the head, growing the linked list if it is full.
+    pub fn shift_new_growing(&mut self, elem: T) -> usize {
+        if self.is_full() {
+            self.reserve(self.len());
+        }
+        self.shift_new(elem)
+            .expect("the linked list has room after growing")
+    }
+
     /// Shifts the element at the given index to the head.
     pub fn shift(&mut self, index: usize) {
         match self.head {
-            Some(head) if head != index => {
-                if let Some(node) = self.arena.at(index) {
-                    let node_prev = node.prev;
-                    let node_next = node.next;
-                    if let Some(prev) = node_prev {
-                        let prev_elem = self.arena.at_mut(prev).unwrap();
-                        prev_elem.next = node_next;
-                    }
-
-                    if let Some(next) = node_next {
-                        let next_elem = self.arena.at_mut(next).unwrap();
-                        next_elem.prev = node_prev;
-                    }
-                }
-
-                if let Some(node) = self.arena.at_mut(index) {
-                    node.prev = None;
-                    node.next = self.head;
-                    self.head = Some(index);
+            Some(head) if head != index && self.arena.at(index).is_some() => {
+                self.unlink(index);
+                let old_head = self.head;
+                let node = self.arena.at_mut(index).unwrap();
+                node.prev = None;
+                node.next = old_head;
+                if let Some(old_head) = old_head {
+                    self.arena.at_mut(old_head).unwrap().prev = Some(index);
+                } else {
+                    self.tail = Some(index);
                 }
+                self.head = Some(index);
             }
             _ => {}
         }
     }
 
+    /// Removes the element at the given index.
+    pub fn remove(&mut self, index: usize) -> Option<T> {
+        if self.arena.at(index).is_some() {
+            self.unlink(index);
+            self.arena.remove(index).map(|node| node.value)
+        } else {
+            None
+        }
+    }
+
+    /// Detaches the node at the given index from its neighbours, keeping the
+    /// node itself in the arena.
+    fn unlink(&mut self, index: usize) {
+        let (prev, next) = match self.arena.at(index) {
+            Some(node) => (node.prev, node.next),
+            None => return,
+        };
+        match prev {
+            Some(prev) => self.arena.at_mut(prev).unwrap().next = next,
+            None => self.head = next,
+        }
+        match next {
+            Some(next) => self.arena.at_mut(next).unwrap().prev = prev,
+            None => self.tail = prev,
+        }
+    }
+
     /// Returns the element present at the head.
     pub fn top(&self) -> Option<&T> {
         if let Some(head) = self.head {
@@ -73,6 +73,30 @@ impl<T> GenArena<T> {
         }
     }
 
+    /// Returns the total number of elements the arena can hold.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Grows the arena by `additional` free slots.
+    pub fn reserve(&mut self, additional: usize) {
+        let start = self.capacity;
+        let end = start + additional;
+        self.elements.reserve_exact(additional);
+        for i in start..end {
+            let next = if i + 1 < end {
+                Some(i + 1)
+            } else {
+                self.free_head
+            };
+            self.elements.push(GenArenaElem::Free { next });
+        }
+        if additional > 0 {
+            self.free_head = Some(start);
+        }
+        self.capacity = end;
+    }
+
     /// Pushes the given `elem` into the arena and returns its index.
     ///
     /// Returns Error if the arena is full.
@@ -1,10 +1,12 @@
 use super::bindings::{XXH3_64bits, XXH3_64bits_withSeed};
 use std::os::raw::c_void;
 
-pub fn xxhash_64(data: &str) -> u64 {
+pub fn xxhash_64<T: AsRef<[u8]> + ?Sized>(data: &T) -> u64 {
+    let data = data.as_ref();
     unsafe { XXH3_64bits(data.as_ptr() as *const c_void, data.len()) }
 }
 
-pub fn xxhash_64_with_seed(data: &str, seed: u64) -> u64 {
+pub fn xxhash_64_with_seed<T: AsRef<[u8]> + ?Sized>(data: &T, seed: u64) -> u64 {
+    let data = data.as_ref();
     unsafe { XXH3_64bits_withSeed(data.as_ptr() as *const c_void, data.len(), seed) }
 }
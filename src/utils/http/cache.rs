@@ -0,0 +1,225 @@
+use super::{
+    DeleteResponse, EntryRequestBody, GetErrorResponse, GetQuery, GetResponse, HttpError, KeyQuery,
+    PutQuery, PutResponse,
+};
+use crate::{
+    cache::{Cache, Versioned},
+    rpc::{self, Entry, Key, Value},
+    CacheServer, HTTPServer,
+};
+use actix_web::{http::header, web, HttpRequest, HttpResponse, HttpResponseBuilder};
+use bytes::Bytes;
+use tonic::Code;
+
+/// Header holding the time to live of an entry in milliseconds, both when
+/// storing it and when reading it back.
+const TTL_HEADER: &str = "X-TTL";
+
+/// Registers the routes of the entries.
+pub(crate) fn configure<C>(cfg: &mut web::ServiceConfig)
+where
+    C: Cache<Vec<u8>, Versioned<Bytes>> + Send + Sync + 'static,
+{
+    super::limits(cfg);
+    cfg.route("/entry", web::post().to(save::<C>)).service(
+        web::resource("/entry/{key}")
+            .route(web::get().to(get::<C>))
+            .route(web::head().to(exists::<C>))
+            .route(web::put().to(save_raw::<C>))
+            .route(web::delete().to(remove::<C>)),
+    );
+}
+
+/// Whether the client asked for the raw bytes of the value.
+fn accepts_octet_stream(req: &HttpRequest) -> bool {
+    req.headers()
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/octet-stream"))
+}
+
+fn error(mut response: HttpResponseBuilder, error: HttpError) -> HttpResponse {
+    response.json(GetErrorResponse { error })
+}
+
+/// Returns the time to live given by the header, which takes precedence over
+/// the one given in the query.
+fn ttl_from(req: &HttpRequest, query_ttl: u64) -> Result<u64, HttpResponse> {
+    match req.headers().get(TTL_HEADER) {
+        Some(ttl) => ttl
+            .to_str()
+            .ok()
+            .and_then(|ttl| ttl.trim().parse().ok())
+            .ok_or_else(|| error(HttpResponse::BadRequest(), HttpError::BadRequest)),
+        None => Ok(query_ttl),
+    }
+}
+
+/// Looks up the entry along with the milliseconds it has left to live, if it
+/// expires.
+async fn lookup<C>(
+    server: &CacheServer<C, HTTPServer>,
+    key: &Vec<u8>,
+) -> Option<(Versioned<Bytes>, Option<u128>)>
+where
+    C: Cache<Vec<u8>, Versioned<Bytes>> + Send + Sync + 'static,
+{
+    let (value, ttl) = server.lookup(key).await?;
+    Some((value, ttl.map(|ttl| ttl.as_millis())))
+}
+
+async fn put_entry<C>(server: &CacheServer<C, HTTPServer>, entry: Entry) -> HttpResponse
+where
+    C: Cache<Vec<u8>, Versioned<Bytes>> + Send + Sync + 'static,
+{
+    match server.put_entry(entry).await {
+        Ok(_) => HttpResponse::Ok().json(PutResponse { success: true }),
+        Err(status) => match status.code() {
+            Code::AlreadyExists => error(HttpResponse::Conflict(), HttpError::KeyExists),
+            Code::NotFound => error(HttpResponse::NotFound(), HttpError::KeyNotFound),
+            // The entry does not fit in the cache
+            Code::Internal => error(HttpResponse::PayloadTooLarge(), HttpError::EntryTooLarge),
+            _ => error(HttpResponse::BadRequest(), HttpError::BadRequest),
+        },
+    }
+}
+
+async fn get<C>(
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+    query: web::Query<GetQuery>,
+    server: web::Data<CacheServer<C, HTTPServer>>,
+) -> HttpResponse
+where
+    C: Cache<Vec<u8>, Versioned<Bytes>> + Send + Sync + 'static,
+{
+    let key = match query.encoding.decode_path(path) {
+        Some(key) => key,
+        None => return error(HttpResponse::BadRequest(), HttpError::InvalidEncoding),
+    };
+    let (value, ttl) = match lookup(&server, &key).await {
+        Some(found) => found,
+        None => return error(HttpResponse::NotFound(), HttpError::KeyNotFound),
+    };
+    let mut response = HttpResponse::Ok();
+    if let Some(ttl) = ttl {
+        response.insert_header((TTL_HEADER, ttl.to_string()));
+    }
+    if accepts_octet_stream(&req) {
+        return response
+            .content_type("application/octet-stream")
+            .body(value.value);
+    }
+    match query.encoding.encode(&value.value) {
+        Some(encoded) => response.json(GetResponse {
+            value: encoded,
+            version: value.version,
+        }),
+        None => error(HttpResponse::NotAcceptable(), HttpError::InvalidEncoding),
+    }
+}
+
+/// Tells whether the key is present without sending its value.
+async fn exists<C>(
+    path: web::Path<(String,)>,
+    query: web::Query<KeyQuery>,
+    server: web::Data<CacheServer<C, HTTPServer>>,
+) -> HttpResponse
+where
+    C: Cache<Vec<u8>, Versioned<Bytes>> + Send + Sync + 'static,
+{
+    let key = match query.encoding.decode_path(path) {
+        Some(key) => key,
+        None => return HttpResponse::BadRequest().finish(),
+    };
+    match lookup(&server, &key).await {
+        Some((_, ttl)) => {
+            let mut response = HttpResponse::Ok();
+            if let Some(ttl) = ttl {
+                response.insert_header((TTL_HEADER, ttl.to_string()));
+            }
+            response.finish()
+        }
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+async fn save<C>(
+    req: HttpRequest,
+    body: web::Json<EntryRequestBody>,
+    server: web::Data<CacheServer<C, HTTPServer>>,
+) -> HttpResponse
+where
+    C: Cache<Vec<u8>, Versioned<Bytes>> + Send + Sync + 'static,
+{
+    let entry_req = body.into_inner();
+    let ttl = match ttl_from(&req, entry_req.ttl) {
+        Ok(ttl) => ttl,
+        Err(response) => return response,
+    };
+    let encoding = entry_req.encoding;
+    let (key, value) = match (
+        encoding.decode(entry_req.key),
+        encoding.decode(entry_req.value),
+    ) {
+        (Some(key), Some(value)) => (key, value),
+        _ => return error(HttpResponse::BadRequest(), HttpError::InvalidEncoding),
+    };
+    let entry = Entry {
+        key: Some(Key { key }),
+        value: Some(Value {
+            value: value.into(),
+        }),
+        ttl,
+        mode: rpc::WriteMode::from(entry_req.mode).into(),
+        version: 0,
+    };
+    put_entry(&server, entry).await
+}
+
+/// Stores the raw request body as the value of the key.
+async fn save_raw<C>(
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+    query: web::Query<PutQuery>,
+    body: web::Bytes,
+    server: web::Data<CacheServer<C, HTTPServer>>,
+) -> HttpResponse
+where
+    C: Cache<Vec<u8>, Versioned<Bytes>> + Send + Sync + 'static,
+{
+    let ttl = match ttl_from(&req, query.ttl) {
+        Ok(ttl) => ttl,
+        Err(response) => return response,
+    };
+    let key = match query.encoding.decode_path(path) {
+        Some(key) => key,
+        None => return error(HttpResponse::BadRequest(), HttpError::InvalidEncoding),
+    };
+    let entry = Entry {
+        key: Some(Key { key }),
+        value: Some(Value { value: body }),
+        ttl,
+        mode: rpc::WriteMode::from(query.mode).into(),
+        version: 0,
+    };
+    put_entry(&server, entry).await
+}
+
+async fn remove<C>(
+    path: web::Path<(String,)>,
+    query: web::Query<KeyQuery>,
+    server: web::Data<CacheServer<C, HTTPServer>>,
+) -> HttpResponse
+where
+    C: Cache<Vec<u8>, Versioned<Bytes>> + Send + Sync + 'static,
+{
+    let key = match query.encoding.decode_path(path) {
+        Some(key) => key,
+        None => return error(HttpResponse::BadRequest(), HttpError::InvalidEncoding),
+    };
+    match server.delete_entry(key).await {
+        Some(_) => HttpResponse::Ok().json(DeleteResponse { success: true }),
+        None => error(HttpResponse::NotFound(), HttpError::KeyNotFound),
+    }
+}
@@ -1,21 +1,77 @@
-use super::{EntryRequestBody, GetErrorResponse, GetResponse, HttpError, PutResponse};
+use super::{
+    DeleteResponse, EntryRequestBody, GetErrorResponse, GetQuery, GetResponse, HttpError, KeyQuery,
+    PutQuery, PutResponse,
+};
 use crate::{
-    rpc::{Entry, Key, Value},
+    rpc::{self, Entry, Key, Value},
     CacheClusterServer, HTTPServer,
 };
-use actix_web::{get, post, web, HttpResponse, Responder};
+use actix_web::{delete, get, http::header, post, put, web, HttpRequest, HttpResponse, Responder};
+use tonic::Code;
+
+/// Whether the client asked for the raw bytes of the value.
+fn accepts_octet_stream(req: &HttpRequest) -> bool {
+    req.headers()
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/octet-stream"))
+}
+
+fn invalid_encoding() -> HttpResponse {
+    HttpResponse::BadRequest().json(GetErrorResponse {
+        error: HttpError::InvalidEncoding,
+    })
+}
+
+async fn put_entry(cluster: &CacheClusterServer<HTTPServer>, entry: Entry) -> HttpResponse {
+    match cluster.network.lock().await.put_entry(entry).await {
+        Ok(_) => HttpResponse::Ok().json(PutResponse { success: true }),
+        Err(status) if status.code() == Code::AlreadyExists => {
+            HttpResponse::Conflict().json(GetErrorResponse {
+                error: HttpError::KeyExists,
+            })
+        }
+        Err(status) if status.code() == Code::NotFound => {
+            HttpResponse::NotFound().json(GetErrorResponse {
+                error: HttpError::KeyNotFound,
+            })
+        }
+        // TODO: Provide more details about the errors
+        _ => HttpResponse::BadRequest().json(GetErrorResponse {
+            error: HttpError::BadRequest,
+        }),
+    }
+}
 
 #[get("/entry/{key}")]
 pub(crate) async fn get(
+    req: HttpRequest,
     path: web::Path<(String,)>,
+    query: web::Query<GetQuery>,
     cluster: web::Data<CacheClusterServer<HTTPServer>>,
 ) -> impl Responder {
-    let key = &path.0;
-    let key = Key { key: key.clone() };
+    let key = match query.encoding.decode_path(path) {
+        Some(key) => Key { key },
+        None => return invalid_encoding(),
+    };
     match cluster.network.lock().await.get_value(key).await {
         Ok(resp) => {
-            if let Some(value) = resp.into_inner().value {
-                HttpResponse::Ok().json(GetResponse { value: value.value })
+            let resp = resp.into_inner();
+            if let Some(value) = resp.value {
+                if accepts_octet_stream(&req) {
+                    return HttpResponse::Ok()
+                        .content_type("application/octet-stream")
+                        .body(value.value);
+                }
+                match query.encoding.encode(&value.value) {
+                    Some(value) => HttpResponse::Ok().json(GetResponse {
+                        value,
+                        version: resp.version,
+                    }),
+                    None => HttpResponse::NotAcceptable().json(GetErrorResponse {
+                        error: HttpError::InvalidEncoding,
+                    }),
+                }
             } else {
                 HttpResponse::InternalServerError().json(GetErrorResponse {
                     error: HttpError::UnknownError,
@@ -34,17 +90,62 @@ pub(crate) async fn save(
     cluster: web::Data<CacheClusterServer<HTTPServer>>,
 ) -> impl Responder {
     let entry_req = path.into_inner();
+    let encoding = entry_req.encoding;
+    let (key, value) = match (
+        encoding.decode(entry_req.key),
+        encoding.decode(entry_req.value),
+    ) {
+        (Some(key), Some(value)) => (key, value),
+        _ => return invalid_encoding(),
+    };
     let entry = Entry {
-        key: Some(Key { key: entry_req.key }),
+        key: Some(Key { key }),
         value: Some(Value {
-            value: entry_req.value,
+            value: value.into(),
         }),
+        ttl: entry_req.ttl,
+        mode: rpc::WriteMode::from(entry_req.mode).into(),
+        version: 0,
     };
-    match cluster.network.lock().await.put_entry(entry).await {
-        Ok(_) => HttpResponse::Ok().json(PutResponse { success: true }),
-        // TODO: Provide more details about the errors
-        _ => HttpResponse::BadRequest().json(GetErrorResponse {
-            error: HttpError::BadRequest,
+    put_entry(&cluster, entry).await
+}
+
+/// Stores the raw request body as the value of the key.
+#[put("/entry/{key}")]
+pub(crate) async fn save_raw(
+    path: web::Path<(String,)>,
+    query: web::Query<PutQuery>,
+    body: web::Bytes,
+    cluster: web::Data<CacheClusterServer<HTTPServer>>,
+) -> impl Responder {
+    let key = match query.encoding.decode_path(path) {
+        Some(key) => key,
+        None => return invalid_encoding(),
+    };
+    let entry = Entry {
+        key: Some(Key { key }),
+        value: Some(Value { value: body }),
+        ttl: query.ttl,
+        mode: rpc::WriteMode::from(query.mode).into(),
+        version: 0,
+    };
+    put_entry(&cluster, entry).await
+}
+
+#[delete("/entry/{key}")]
+pub(crate) async fn remove(
+    path: web::Path<(String,)>,
+    query: web::Query<KeyQuery>,
+    cluster: web::Data<CacheClusterServer<HTTPServer>>,
+) -> impl Responder {
+    let key = match query.encoding.decode_path(path) {
+        Some(key) => Key { key },
+        None => return invalid_encoding(),
+    };
+    match cluster.network.lock().await.delete_entry(key).await {
+        Ok(_) => HttpResponse::Ok().json(DeleteResponse { success: true }),
+        _ => HttpResponse::NotFound().json(GetErrorResponse {
+            error: HttpError::KeyNotFound,
         }),
     }
 }
@@ -1,17 +1,36 @@
+pub mod cache;
 pub mod cluster;
 
+use crate::rpc;
+use actix_web::web;
+use base64::{engine::general_purpose::STANDARD, Engine};
 use serde::{Deserialize, Serialize};
 
+/// Largest request body accepted, which leaves room for the key within the
+/// 4 MiB messages the gRPC servers accept.
+const MAX_BODY_LEN: usize = (4 << 20) - (64 << 10);
+
+/// Raises the limits on the request bodies from the defaults of actix, which
+/// are far below the values the other frontends store.
+pub(crate) fn limits(cfg: &mut web::ServiceConfig) {
+    cfg.app_data(web::PayloadConfig::new(MAX_BODY_LEN))
+        .app_data(web::JsonConfig::default().limit(MAX_BODY_LEN));
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 struct GetResponse {
     value: String,
+    version: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum HttpError {
     KeyNotFound,
+    KeyExists,
     UnknownError,
     BadRequest,
+    InvalidEncoding,
+    EntryTooLarge,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -24,8 +43,101 @@ struct PutResponse {
     success: bool,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+struct DeleteResponse {
+    success: bool,
+}
+
+/// How the keys and values are represented in the JSON bodies.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum Encoding {
+    /// Plain text, which only fits values that are valid UTF-8
+    #[default]
+    Utf8,
+    /// Standard base64, which fits any binary value
+    Base64,
+}
+
+impl Encoding {
+    fn encode(self, data: &[u8]) -> Option<String> {
+        match self {
+            Self::Utf8 => String::from_utf8(data.to_vec()).ok(),
+            Self::Base64 => Some(STANDARD.encode(data)),
+        }
+    }
+
+    fn decode(self, data: String) -> Option<Vec<u8>> {
+        match self {
+            Self::Utf8 => Some(data.into_bytes()),
+            Self::Base64 => STANDARD.decode(data).ok(),
+        }
+    }
+
+    /// Decodes the key given in the path of the request.
+    fn decode_path(self, path: web::Path<(String,)>) -> Option<Vec<u8>> {
+        self.decode(path.into_inner().0)
+    }
+}
+
+/// Whether an entry is stored depending on the key being present.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum WriteMode {
+    #[default]
+    Set,
+    /// Only stores the entry if the key is not present
+    Add,
+    /// Only stores the entry if the key is present
+    Replace,
+}
+
+impl From<WriteMode> for rpc::WriteMode {
+    fn from(mode: WriteMode) -> Self {
+        match mode {
+            WriteMode::Set => Self::Set,
+            WriteMode::Add => Self::Add,
+            WriteMode::Replace => Self::Replace,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 struct EntryRequestBody {
     key: String,
     value: String,
+    /// Time to live in milliseconds
+    #[serde(default)]
+    ttl: u64,
+    /// Encoding of both the key and the value
+    #[serde(default)]
+    encoding: Encoding,
+    #[serde(default)]
+    mode: WriteMode,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GetQuery {
+    /// Encoding of both the key in the path and the returned value
+    #[serde(default)]
+    encoding: Encoding,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct KeyQuery {
+    /// Encoding of the key in the path
+    #[serde(default)]
+    encoding: Encoding,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct PutQuery {
+    /// Time to live in milliseconds
+    #[serde(default)]
+    ttl: u64,
+    #[serde(default)]
+    mode: WriteMode,
+    /// Encoding of the key in the path, as the value is the raw body
+    #[serde(default)]
+    encoding: Encoding,
 }
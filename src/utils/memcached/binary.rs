@@ -0,0 +1,466 @@
+use super::{ttl_from_exptime, Command, Reply, StoreMode, MAX_KEY_LEN, MAX_VALUE_LEN};
+use bytes::{Buf, BufMut, Bytes};
+
+/// First byte of every request, which tells the binary protocol apart from
+/// the text one.
+pub const REQUEST_MAGIC: u8 = 0x80;
+const RESPONSE_MAGIC: u8 = 0x81;
+
+const HEADER_LEN: usize = 24;
+
+/// Largest body a client may send, leaving room for the key and the extras
+/// besides the value.
+const MAX_BODY_LEN: usize = MAX_VALUE_LEN + MAX_KEY_LEN + 32;
+
+/// Expiration time of an increment or decrement which must not create the
+/// counter.
+const NO_INITIAL: u32 = 0xffff_ffff;
+
+mod opcode {
+    pub const GET: u8 = 0x00;
+    pub const SET: u8 = 0x01;
+    pub const ADD: u8 = 0x02;
+    pub const REPLACE: u8 = 0x03;
+    pub const DELETE: u8 = 0x04;
+    pub const INCREMENT: u8 = 0x05;
+    pub const DECREMENT: u8 = 0x06;
+    pub const QUIT: u8 = 0x07;
+    pub const GETQ: u8 = 0x09;
+    pub const NOOP: u8 = 0x0a;
+    pub const VERSION: u8 = 0x0b;
+    pub const GETK: u8 = 0x0c;
+    pub const GETKQ: u8 = 0x0d;
+    pub const STAT: u8 = 0x10;
+    pub const SETQ: u8 = 0x11;
+    pub const ADDQ: u8 = 0x12;
+    pub const REPLACEQ: u8 = 0x13;
+    pub const DELETEQ: u8 = 0x14;
+    pub const INCREMENTQ: u8 = 0x15;
+    pub const DECREMENTQ: u8 = 0x16;
+    pub const QUITQ: u8 = 0x17;
+    pub const TOUCH: u8 = 0x1c;
+}
+
+mod status {
+    pub const NO_ERROR: u16 = 0x00;
+    pub const KEY_NOT_FOUND: u16 = 0x01;
+    pub const KEY_EXISTS: u16 = 0x02;
+    pub const VALUE_TOO_LARGE: u16 = 0x03;
+    pub const INVALID_ARGUMENTS: u16 = 0x04;
+    pub const NON_NUMERIC: u16 = 0x06;
+    pub const UNKNOWN_COMMAND: u16 = 0x81;
+}
+
+/// Fields of a request which tell how to answer it.
+pub struct Header {
+    opcode: u8,
+    opaque: u32,
+    key: Bytes,
+}
+
+/// A command of the binary protocol along with how to answer it.
+pub struct Request {
+    pub header: Header,
+    /// A malformed command is the reply it gets
+    pub command: Result<Command, Reply>,
+}
+
+impl Header {
+    /// Whether the reply is left out when the command succeeds, or for the
+    /// gets when the key is not found.
+    fn quiet(&self) -> bool {
+        matches!(
+            self.opcode,
+            opcode::GETQ
+                | opcode::GETKQ
+                | opcode::SETQ
+                | opcode::ADDQ
+                | opcode::REPLACEQ
+                | opcode::DELETEQ
+                | opcode::INCREMENTQ
+                | opcode::DECREMENTQ
+                | opcode::QUITQ
+        )
+    }
+}
+
+/// Parses a request from the start of the buffer, and returns it along with
+/// the number of bytes it took, or `None` if the buffer does not hold a whole
+/// request yet. The bytes taken go past the end of the buffer for a body too
+/// large to be read.
+pub fn parse(buf: &[u8]) -> Result<Option<(Request, usize)>, &'static str> {
+    if buf.len() < HEADER_LEN {
+        return Ok(None);
+    }
+    let mut header = &buf[..HEADER_LEN];
+    if header.get_u8() != REQUEST_MAGIC {
+        return Err("invalid magic");
+    }
+    let opcode = header.get_u8();
+    let key_len = header.get_u16() as usize;
+    let extras_len = header.get_u8() as usize;
+    header.advance(3); // Data type and vbucket
+    let body_len = header.get_u32() as usize;
+    let opaque = header.get_u32();
+    let cas = header.get_u64();
+    if body_len > MAX_BODY_LEN {
+        // The body is swallowed and the next requests served, like memcached
+        let request = Request {
+            header: Header {
+                opcode,
+                opaque,
+                key: Bytes::new(),
+            },
+            command: Err(Reply::TooLarge),
+        };
+        return Ok(Some((request, HEADER_LEN + body_len)));
+    }
+    if buf.len() < HEADER_LEN + body_len {
+        return Ok(None);
+    }
+    let len = HEADER_LEN + body_len;
+    if extras_len + key_len > body_len {
+        return Err("invalid body length");
+    }
+
+    let body = &buf[HEADER_LEN..len];
+    let mut extras = &body[..extras_len];
+    let key = Bytes::copy_from_slice(&body[extras_len..extras_len + key_len]);
+    let value = &body[extras_len + key_len..];
+    let invalid = Err(Reply::ClientError("Invalid arguments"));
+
+    let command = match opcode {
+        _ if key.len() > MAX_KEY_LEN => invalid,
+        opcode::GET | opcode::GETQ | opcode::GETK | opcode::GETKQ => {
+            if extras_len == 0 && !key.is_empty() {
+                Ok(Command::Get {
+                    keys: vec![key.clone()],
+                })
+            } else {
+                invalid
+            }
+        }
+        opcode::SET
+        | opcode::SETQ
+        | opcode::ADD
+        | opcode::ADDQ
+        | opcode::REPLACE
+        | opcode::REPLACEQ => {
+            if extras_len == 8 && !key.is_empty() {
+                let flags = extras.get_u32();
+                let exptime = extras.get_u32();
+                let mode = match opcode {
+                    opcode::ADD | opcode::ADDQ => StoreMode::Add,
+                    _ if cas != 0 => StoreMode::Cas(cas),
+                    opcode::REPLACE | opcode::REPLACEQ => StoreMode::Replace,
+                    _ => StoreMode::Set,
+                };
+                Ok(Command::Store {
+                    mode,
+                    key: key.clone(),
+                    flags,
+                    ttl: ttl_from_exptime(exptime.into()),
+                    value: Bytes::copy_from_slice(value),
+                })
+            } else {
+                invalid
+            }
+        }
+        opcode::DELETE | opcode::DELETEQ => {
+            // Deleting only if the entry is still at a version is not supported
+            if extras_len == 0 && !key.is_empty() && value.is_empty() && cas == 0 {
+                Ok(Command::Delete { key: key.clone() })
+            } else {
+                invalid
+            }
+        }
+        opcode::INCREMENT | opcode::INCREMENTQ | opcode::DECREMENT | opcode::DECREMENTQ => {
+            if extras_len == 20 && !key.is_empty() && value.is_empty() {
+                let delta = extras.get_u64();
+                let initial = extras.get_u64();
+                let exptime = extras.get_u32();
+                Ok(Command::Counter {
+                    key: key.clone(),
+                    delta,
+                    increment: matches!(opcode, opcode::INCREMENT | opcode::INCREMENTQ),
+                    initial: (exptime != NO_INITIAL).then_some(initial),
+                    ttl: ttl_from_exptime(exptime.into()),
+                })
+            } else {
+                invalid
+            }
+        }
+        opcode::TOUCH => {
+            if extras_len == 4 && !key.is_empty() && value.is_empty() {
+                let exptime = extras.get_u32();
+                Ok(Command::Touch {
+                    key: key.clone(),
+                    ttl: ttl_from_exptime(exptime.into()),
+                })
+            } else {
+                invalid
+            }
+        }
+        opcode::STAT if key.is_empty() => Ok(Command::Stats),
+        opcode::VERSION => Ok(Command::Version),
+        opcode::NOOP => Ok(Command::Noop),
+        opcode::QUIT | opcode::QUITQ => Ok(Command::Quit),
+        _ => Err(Reply::UnknownCommand),
+    };
+    let request = Request {
+        header: Header {
+            opcode,
+            opaque,
+            key,
+        },
+        command,
+    };
+    Ok(Some((request, len)))
+}
+
+fn write_response(
+    out: &mut Vec<u8>,
+    request: &Header,
+    status: u16,
+    cas: u64,
+    extras: &[u8],
+    key: &[u8],
+    value: &[u8],
+) {
+    out.put_u8(RESPONSE_MAGIC);
+    out.put_u8(request.opcode);
+    out.put_u16(key.len() as u16);
+    out.put_u8(extras.len() as u8);
+    out.put_u8(0); // Data type
+    out.put_u16(status);
+    out.put_u32((extras.len() + key.len() + value.len()) as u32);
+    out.put_u32(request.opaque);
+    out.put_u64(cas);
+    out.extend_from_slice(extras);
+    out.extend_from_slice(key);
+    out.extend_from_slice(value);
+}
+
+fn write_status(out: &mut Vec<u8>, request: &Header, status: u16, message: &str) {
+    write_response(out, request, status, 0, &[], &[], message.as_bytes());
+}
+
+/// Writes the reply to the request, unless the request is quiet about it.
+pub fn write_reply(request: &Header, reply: &Reply, out: &mut Vec<u8>) {
+    let quiet = request.quiet();
+    match reply {
+        Reply::Values(values) => match values.first() {
+            Some((key, value)) => {
+                let key: &[u8] = match request.opcode {
+                    opcode::GETK | opcode::GETKQ => &key[..],
+                    _ => &[],
+                };
+                let flags = value.flags.to_be_bytes();
+                write_response(out, request, 0, value.version, &flags, key, &value.value);
+            }
+            None if quiet => {}
+            None => match request.opcode {
+                opcode::GETK => write_response(
+                    out,
+                    request,
+                    status::KEY_NOT_FOUND,
+                    0,
+                    &[],
+                    &request.key,
+                    b"Not found",
+                ),
+                _ => write_status(out, request, status::KEY_NOT_FOUND, "Not found"),
+            },
+        },
+        Reply::Stored(version) => {
+            if !quiet {
+                write_response(out, request, status::NO_ERROR, *version, &[], &[], &[]);
+            }
+        }
+        // Add and replace tell which of their conditions does not hold
+        Reply::NotStored => match request.opcode {
+            opcode::ADD | opcode::ADDQ => {
+                write_status(out, request, status::KEY_EXISTS, "Data exists for key.")
+            }
+            _ => write_status(out, request, status::KEY_NOT_FOUND, "Not found"),
+        },
+        Reply::Exists => write_status(out, request, status::KEY_EXISTS, "Data exists for key."),
+        Reply::NotFound => write_status(out, request, status::KEY_NOT_FOUND, "Not found"),
+        Reply::Deleted | Reply::Touched | Reply::Done => {
+            if !quiet {
+                write_status(out, request, status::NO_ERROR, "");
+            }
+        }
+        Reply::Counter { value, version } => {
+            if !quiet {
+                let value = value.to_be_bytes();
+                write_response(out, request, status::NO_ERROR, *version, &[], &[], &value);
+            }
+        }
+        Reply::NonNumeric => write_status(
+            out,
+            request,
+            status::NON_NUMERIC,
+            "Non-numeric server-side value for incr or decr",
+        ),
+        Reply::TooLarge => write_status(out, request, status::VALUE_TOO_LARGE, "Too large."),
+        // One response per statistic, ended by an empty one
+        Reply::Stats(stats) => {
+            for (name, value) in stats {
+                let (name, value) = (name.as_bytes(), value.as_bytes());
+                write_response(out, request, status::NO_ERROR, 0, &[], name, value);
+            }
+            write_status(out, request, status::NO_ERROR, "");
+        }
+        Reply::Version => write_status(out, request, status::NO_ERROR, env!("CARGO_PKG_VERSION")),
+        Reply::UnknownCommand => {
+            write_status(out, request, status::UNKNOWN_COMMAND, "Unknown command")
+        }
+        Reply::ClientError(msg) => write_status(out, request, status::INVALID_ARGUMENTS, msg),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn request(opcode: u8, cas: u64, extras: &[u8], key: &[u8], value: &[u8]) -> Vec<u8> {
+        let mut out = vec![REQUEST_MAGIC, opcode];
+        out.put_u16(key.len() as u16);
+        out.put_u8(extras.len() as u8);
+        out.put_u8(0);
+        out.put_u16(0);
+        out.put_u32((extras.len() + key.len() + value.len()) as u32);
+        out.put_u32(0xcafe);
+        out.put_u64(cas);
+        out.extend_from_slice(extras);
+        out.extend_from_slice(key);
+        out.extend_from_slice(value);
+        out
+    }
+
+    #[test]
+    fn waits_for_the_whole_request() {
+        let mut extras = 7u32.to_be_bytes().to_vec();
+        extras.extend_from_slice(&60u32.to_be_bytes());
+        let input = request(opcode::SETQ, 0, &extras, b"k", b"abc");
+        for end in 0..input.len() {
+            assert!(matches!(parse(&input[..end]), Ok(None)));
+        }
+        let (request, taken) = parse(&input).unwrap().unwrap();
+        assert_eq!(taken, input.len());
+        assert!(request.header.quiet());
+        assert_eq!(
+            (request.header.opaque, &request.header.key[..]),
+            (0xcafe, &b"k"[..])
+        );
+        match request.command {
+            Ok(Command::Store {
+                mode,
+                key,
+                flags,
+                ttl,
+                value,
+            }) => {
+                assert!(mode == StoreMode::Set);
+                assert_eq!(
+                    (&key[..], flags, ttl, &value[..]),
+                    (&b"k"[..], 7, Some(Duration::from_secs(60)), &b"abc"[..])
+                );
+            }
+            _ => panic!("expected a store"),
+        }
+    }
+
+    #[test]
+    fn parses_pipelined_requests() {
+        let mut counter = 2u64.to_be_bytes().to_vec();
+        counter.extend_from_slice(&10u64.to_be_bytes());
+        counter.extend_from_slice(&NO_INITIAL.to_be_bytes());
+        let mut input = request(opcode::GETKQ, 0, &[], b"a", &[]);
+        input.extend(request(opcode::SET, 42, &[0; 8], b"b", b"v"));
+        input.extend(request(opcode::DECREMENT, 0, &counter, b"n", &[]));
+        input.extend(request(opcode::NOOP, 0, &[], &[], &[]));
+
+        let mut buf = &input[..];
+        let mut commands = vec![];
+        while let Some((request, taken)) = parse(buf).unwrap() {
+            commands.push(request.command.ok().unwrap());
+            buf = &buf[taken..];
+        }
+        assert!(buf.is_empty());
+        assert!(matches!(&commands[0], Command::Get { keys } if keys == &["a"]));
+        assert!(matches!(
+            &commands[1],
+            Command::Store {
+                mode: StoreMode::Cas(42),
+                ttl: None,
+                ..
+            }
+        ));
+        assert!(matches!(
+            &commands[2],
+            Command::Counter {
+                delta: 2,
+                increment: false,
+                initial: None,
+                ..
+            }
+        ));
+        assert!(matches!(&commands[3], Command::Noop));
+    }
+
+    #[test]
+    fn answers_malformed_requests() {
+        let command = |input: Vec<u8>| parse(&input).unwrap().unwrap().0.command;
+        assert!(matches!(
+            command(request(opcode::GET, 0, &[0; 4], b"k", &[])),
+            Err(Reply::ClientError(_))
+        ));
+        assert!(matches!(
+            command(request(opcode::DELETE, 1, &[], b"k", &[])),
+            Err(Reply::ClientError(_))
+        ));
+        assert!(matches!(
+            command(request(0x7f, 0, &[], &[], &[])),
+            Err(Reply::UnknownCommand)
+        ));
+
+        let mut input = request(opcode::GET, 0, &[], b"k", &[]);
+        input[0] = RESPONSE_MAGIC;
+        assert!(matches!(parse(&input), Err("invalid magic")));
+        let mut input = request(opcode::GET, 0, &[], b"k", &[]);
+        input[2..4].copy_from_slice(&2u16.to_be_bytes());
+        assert!(matches!(parse(&input), Err("invalid body length")));
+    }
+
+    #[test]
+    fn swallows_bodies_too_large() {
+        let mut input = request(opcode::SET, 0, &[0; 8], b"k", &[]);
+        input[8..12].copy_from_slice(&(MAX_BODY_LEN as u32 + 1).to_be_bytes());
+        let (request, taken) = parse(&input).unwrap().unwrap();
+        assert!(matches!(request.command, Err(Reply::TooLarge)));
+        assert_eq!(taken, HEADER_LEN + MAX_BODY_LEN + 1);
+        let mut out = vec![];
+        write_reply(&request.header, &Reply::TooLarge, &mut out);
+        assert_eq!(out[7], status::VALUE_TOO_LARGE as u8);
+        assert_eq!(&out[12..16], &0xcafeu32.to_be_bytes());
+    }
+
+    #[test]
+    fn quiet_gets_leave_misses_out() {
+        let (quiet, _) = parse(&request(opcode::GETQ, 0, &[], b"k", &[]))
+            .unwrap()
+            .unwrap();
+        let (loud, _) = parse(&request(opcode::GETK, 0, &[], b"k", &[]))
+            .unwrap()
+            .unwrap();
+        let mut out = vec![];
+        write_reply(&quiet.header, &Reply::Values(vec![]), &mut out);
+        assert!(out.is_empty());
+        write_reply(&loud.header, &Reply::Values(vec![]), &mut out);
+        assert_eq!(out[7], status::KEY_NOT_FOUND as u8);
+        assert_eq!(&out[HEADER_LEN..HEADER_LEN + 1], b"k");
+    }
+}
@@ -0,0 +1,266 @@
+use super::{binary, text, Command, Reply, Stat, Stats, StoreMode};
+use crate::{
+    cache::{Cache, Versioned},
+    CacheServer, CountError, MemcachedServer, Overflow, StoreError, WriteCondition,
+};
+use bytes::{Buf, Bytes, BytesMut};
+use std::{io, sync::Arc};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+/// Serves the commands of a client until it disconnects or quits, in the
+/// protocol told by the first byte it sends.
+pub(crate) async fn serve<C>(
+    server: Arc<CacheServer<C, MemcachedServer>>,
+    stats: Arc<Stats>,
+    mut socket: TcpStream,
+) -> io::Result<()>
+where
+    C: Cache<Vec<u8>, Versioned<Bytes>> + Send + Sync + 'static,
+{
+    stats.connected();
+    let result = serve_connection(&server, &stats, &mut socket).await;
+    stats.disconnected();
+    result
+}
+
+async fn serve_connection<C>(
+    server: &CacheServer<C, MemcachedServer>,
+    stats: &Stats,
+    socket: &mut TcpStream,
+) -> io::Result<()>
+where
+    C: Cache<Vec<u8>, Versioned<Bytes>> + Send + Sync + 'static,
+{
+    let mut buf = BytesMut::with_capacity(4096);
+    if socket.read_buf(&mut buf).await? == 0 {
+        return Ok(());
+    }
+    let binary = buf[0] == binary::REQUEST_MAGIC;
+    let mut out = Vec::new();
+    let mut closing = false;
+    // Bytes of a command still to arrive which are thrown away, such as the
+    // data of a value too large to be stored
+    let mut discarding = 0;
+    loop {
+        let discarded = discarding.min(buf.len());
+        buf.advance(discarded);
+        discarding -= discarded;
+        // Answer every pipelined command already read before writing back
+        while !closing && discarding == 0 {
+            let len = if binary {
+                binary_step(server, stats, &buf, &mut out, &mut closing).await
+            } else {
+                text_step(server, stats, &buf, &mut out, &mut closing).await
+            };
+            match len {
+                Some(len) => {
+                    let taken = len.min(buf.len());
+                    buf.advance(taken);
+                    discarding = len - taken;
+                }
+                None => break,
+            }
+        }
+        if !out.is_empty() {
+            socket.write_all(&out).await?;
+            out.clear();
+        }
+        if closing || socket.read_buf(&mut buf).await? == 0 {
+            return Ok(());
+        }
+    }
+}
+
+/// Answers the text command at the start of the buffer, and returns the
+/// number of bytes it took, or `None` if it is not complete yet.
+async fn text_step<C>(
+    server: &CacheServer<C, MemcachedServer>,
+    stats: &Stats,
+    buf: &[u8],
+    out: &mut Vec<u8>,
+    closing: &mut bool,
+) -> Option<usize>
+where
+    C: Cache<Vec<u8>, Versioned<Bytes>> + Send + Sync + 'static,
+{
+    match text::parse(buf) {
+        Ok(Some((Ok(request), len))) => {
+            *closing = matches!(request.command, Command::Quit);
+            let reply = execute(server, stats, request.command).await;
+            if !request.noreply {
+                text::write_reply(&reply, request.cas, out);
+            }
+            Some(len)
+        }
+        Ok(Some((Err(reply), len))) => {
+            text::write_reply(&reply, false, out);
+            Some(len)
+        }
+        Ok(None) => None,
+        Err(reply) => {
+            text::write_reply(&reply, false, out);
+            *closing = true;
+            None
+        }
+    }
+}
+
+/// Answers the binary request at the start of the buffer, and returns the
+/// number of bytes it took, or `None` if it is not complete yet.
+async fn binary_step<C>(
+    server: &CacheServer<C, MemcachedServer>,
+    stats: &Stats,
+    buf: &[u8],
+    out: &mut Vec<u8>,
+    closing: &mut bool,
+) -> Option<usize>
+where
+    C: Cache<Vec<u8>, Versioned<Bytes>> + Send + Sync + 'static,
+{
+    match binary::parse(buf) {
+        Ok(Some((request, len))) => {
+            let reply = match request.command {
+                Ok(command) => {
+                    *closing = matches!(command, Command::Quit);
+                    execute(server, stats, command).await
+                }
+                Err(reply) => reply,
+            };
+            binary::write_reply(&request.header, &reply, out);
+            Some(len)
+        }
+        Ok(None) => None,
+        // The stream cannot be framed anymore, so the client is dropped
+        Err(_) => {
+            *closing = true;
+            None
+        }
+    }
+}
+
+async fn execute<C>(
+    server: &CacheServer<C, MemcachedServer>,
+    stats: &Stats,
+    command: Command,
+) -> Reply
+where
+    C: Cache<Vec<u8>, Versioned<Bytes>> + Send + Sync + 'static,
+{
+    match command {
+        Command::Get { keys } => {
+            let mut values = Vec::with_capacity(keys.len());
+            for key in keys {
+                stats.record(Stat::CmdGet);
+                match server.lookup(&key.to_vec()).await {
+                    Some((value, _)) => {
+                        stats.record(Stat::GetHits);
+                        values.push((key, value));
+                    }
+                    None => stats.record(Stat::GetMisses),
+                }
+            }
+            Reply::Values(values)
+        }
+        Command::Store {
+            mode,
+            key,
+            flags,
+            ttl,
+            value,
+        } => {
+            stats.record(Stat::CmdSet);
+            let condition = match mode {
+                StoreMode::Set => WriteCondition::Always,
+                StoreMode::Add => WriteCondition::Absent,
+                StoreMode::Replace => WriteCondition::Present,
+                StoreMode::Cas(version) => WriteCondition::Version(version),
+            };
+            let cas = matches!(mode, StoreMode::Cas(_));
+            match server
+                .store_if(condition, key.to_vec(), value, flags, ttl, None)
+                .await
+            {
+                Ok(version) => {
+                    if cas {
+                        stats.record(Stat::CasHits);
+                    }
+                    Reply::Stored(version)
+                }
+                Err(StoreError::Exists(_)) if cas => {
+                    stats.record(Stat::CasBadval);
+                    Reply::Exists
+                }
+                Err(StoreError::NotFound) if cas => {
+                    stats.record(Stat::CasMisses);
+                    Reply::NotFound
+                }
+                Err(StoreError::Exists(_) | StoreError::NotFound | StoreError::Stale(_)) => {
+                    Reply::NotStored
+                }
+                Err(StoreError::TooLarge(_)) => Reply::TooLarge,
+            }
+        }
+        Command::Delete { key } => match server.delete_entry(key.to_vec()).await {
+            Some(_) => {
+                stats.record(Stat::DeleteHits);
+                Reply::Deleted
+            }
+            None => {
+                stats.record(Stat::DeleteMisses);
+                Reply::NotFound
+            }
+        },
+        Command::Counter {
+            key,
+            delta,
+            increment,
+            initial,
+            ttl,
+        } => {
+            let (hits, misses) = if increment {
+                (Stat::IncrHits, Stat::IncrMisses)
+            } else {
+                (Stat::DecrHits, Stat::DecrMisses)
+            };
+            let missing = initial.map(|initial| (initial.into(), ttl));
+            match server
+                .add_to_counter(key.to_vec(), delta, increment, missing, Overflow::Wrap)
+                .await
+            {
+                Ok(counted) => {
+                    stats.record(if counted.created { misses } else { hits });
+                    Reply::Counter {
+                        value: counted.value as u64,
+                        version: counted.version,
+                    }
+                }
+                Err(CountError::NotNumeric) => {
+                    stats.record(hits);
+                    Reply::NonNumeric
+                }
+                Err(CountError::NotFound) => {
+                    stats.record(misses);
+                    Reply::NotFound
+                }
+                // Wrapping counters never overflow
+                Err(CountError::Overflow | CountError::TooLarge(_)) => Reply::TooLarge,
+            }
+        }
+        Command::Touch { key, ttl } => {
+            stats.record(Stat::CmdTouch);
+            if server.touch(key.to_vec(), ttl).await {
+                stats.record(Stat::TouchHits);
+                Reply::Touched
+            } else {
+                stats.record(Stat::TouchMisses);
+                Reply::NotFound
+            }
+        }
+        Command::Stats => Reply::Stats(stats.report()),
+        Command::Version => Reply::Version,
+        Command::Noop | Command::Quit => Reply::Done,
+    }
+}
@@ -0,0 +1,212 @@
+pub mod binary;
+pub mod cache;
+pub mod text;
+
+use crate::cache::Versioned;
+use bytes::Bytes;
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Longest key memcached accepts.
+const MAX_KEY_LEN: usize = 250;
+
+/// Largest value a client may send, same as the default item size of
+/// memcached.
+const MAX_VALUE_LEN: usize = 1 << 20;
+
+/// Expiration times up to 30 days are in seconds from now, longer ones are
+/// Unix timestamps.
+const MAX_RELATIVE_EXPTIME: i64 = 60 * 60 * 24 * 30;
+
+/// Converts a memcached expiration time to a time to live, where `0` means
+/// the entry never expires and a time in the past expires it right away.
+fn ttl_from_exptime(exptime: i64) -> Option<Duration> {
+    if exptime == 0 {
+        return None;
+    }
+    if exptime < 0 {
+        return Some(Duration::ZERO);
+    }
+    let ttl = Duration::from_secs(exptime as u64);
+    if exptime <= MAX_RELATIVE_EXPTIME {
+        return Some(ttl);
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Some(ttl.saturating_sub(now))
+}
+
+/// Condition a storage command stores the entry under.
+#[derive(Clone, Copy, PartialEq)]
+pub enum StoreMode {
+    Set,
+    Add,
+    Replace,
+    /// Only stores the entry if it is still at the given version
+    Cas(u64),
+}
+
+/// A request of either protocol.
+pub enum Command {
+    Get {
+        keys: Vec<Bytes>,
+    },
+    Store {
+        mode: StoreMode,
+        key: Bytes,
+        flags: u32,
+        ttl: Option<Duration>,
+        value: Bytes,
+    },
+    Delete {
+        key: Bytes,
+    },
+    /// Increments or decrements the counter. A missing counter is created
+    /// from the initial value if given, and is not found otherwise.
+    Counter {
+        key: Bytes,
+        delta: u64,
+        increment: bool,
+        initial: Option<u64>,
+        ttl: Option<Duration>,
+    },
+    Touch {
+        key: Bytes,
+        ttl: Option<Duration>,
+    },
+    Stats,
+    Version,
+    Noop,
+    Quit,
+}
+
+/// Outcome of a request, which each protocol writes in its own way.
+pub enum Reply {
+    /// The entries found, skipping the keys which are not present
+    Values(Vec<(Bytes, Versioned<Bytes>)>),
+    /// The entry was stored under the given version
+    Stored(u64),
+    /// The condition of an add or replace does not hold
+    NotStored,
+    /// The entry changed since the version of a cas
+    Exists,
+    NotFound,
+    Deleted,
+    Touched,
+    Counter {
+        value: u64,
+        version: u64,
+    },
+    NonNumeric,
+    TooLarge,
+    Stats(Vec<(&'static str, String)>),
+    Version,
+    /// Done without anything to tell, e.g. a noop
+    Done,
+    /// The command is not known
+    UnknownCommand,
+    ClientError(&'static str),
+}
+
+/// Operations counted by the `stats` command.
+#[derive(Clone, Copy)]
+pub enum Stat {
+    CmdGet,
+    CmdSet,
+    CmdTouch,
+    GetHits,
+    GetMisses,
+    DeleteHits,
+    DeleteMisses,
+    IncrHits,
+    IncrMisses,
+    DecrHits,
+    DecrMisses,
+    CasHits,
+    CasMisses,
+    CasBadval,
+    TouchHits,
+    TouchMisses,
+}
+
+const STAT_NAMES: [&str; 16] = [
+    "cmd_get",
+    "cmd_set",
+    "cmd_touch",
+    "get_hits",
+    "get_misses",
+    "delete_hits",
+    "delete_misses",
+    "incr_hits",
+    "incr_misses",
+    "decr_hits",
+    "decr_misses",
+    "cas_hits",
+    "cas_misses",
+    "cas_badval",
+    "touch_hits",
+    "touch_misses",
+];
+
+/// Statistics of the server, shared by all the connections.
+pub struct Stats {
+    started: Instant,
+    curr_connections: AtomicU64,
+    total_connections: AtomicU64,
+    counters: [AtomicU64; STAT_NAMES.len()],
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Stats {
+            started: Instant::now(),
+            curr_connections: AtomicU64::new(0),
+            total_connections: AtomicU64::new(0),
+            counters: std::array::from_fn(|_| AtomicU64::new(0)),
+        }
+    }
+}
+
+impl Stats {
+    fn record(&self, stat: Stat) {
+        self.counters[stat as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn connected(&self) {
+        self.curr_connections.fetch_add(1, Ordering::Relaxed);
+        self.total_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn disconnected(&self) {
+        self.curr_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Returns the statistics in the order memcached lists them.
+    fn report(&self) -> Vec<(&'static str, String)> {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut report = vec![
+            ("pid", std::process::id().to_string()),
+            ("uptime", self.started.elapsed().as_secs().to_string()),
+            ("time", time.as_secs().to_string()),
+            ("version", env!("CARGO_PKG_VERSION").to_string()),
+            ("pointer_size", usize::BITS.to_string()),
+            (
+                "curr_connections",
+                self.curr_connections.load(Ordering::Relaxed).to_string(),
+            ),
+            (
+                "total_connections",
+                self.total_connections.load(Ordering::Relaxed).to_string(),
+            ),
+        ];
+        for (name, counter) in STAT_NAMES.iter().zip(&self.counters) {
+            report.push((name, counter.load(Ordering::Relaxed).to_string()));
+        }
+        report
+    }
+}
@@ -0,0 +1,370 @@
+use super::{ttl_from_exptime, Command, Reply, StoreMode, MAX_KEY_LEN, MAX_VALUE_LEN};
+use bytes::Bytes;
+use std::{io::Write, str::FromStr};
+
+/// Longest command line a client may send, same as memcached.
+const MAX_LINE_LEN: usize = 2048;
+
+/// A command of the text protocol along with how to answer it.
+pub struct Request {
+    pub command: Command,
+    /// Whether the client asked not to be answered
+    pub noreply: bool,
+    /// Whether the versions are sent along with the values, as for `gets`
+    pub cas: bool,
+}
+
+/// Result of parsing a command, where a malformed command is the reply it
+/// gets.
+type Parsed = Result<Request, Reply>;
+
+fn number<T: FromStr>(arg: &[u8]) -> Option<T> {
+    std::str::from_utf8(arg).ok()?.parse().ok()
+}
+
+fn key(arg: &[u8]) -> Result<Bytes, Reply> {
+    if arg.len() > MAX_KEY_LEN {
+        return Err(Reply::ClientError("bad command line format"));
+    }
+    Ok(Bytes::copy_from_slice(arg))
+}
+
+/// Parses a command from the start of the buffer, and returns it along with
+/// the number of bytes it took, or `None` if the buffer does not hold a whole
+/// command yet. The bytes taken by a value too large to be stored can go past
+/// the end of the buffer, as its data is discarded as it arrives. An error is
+/// returned when the rest of the input can no longer be made sense of, after
+/// which the connection is closed.
+pub fn parse(buf: &[u8]) -> Result<Option<(Parsed, usize)>, Reply> {
+    let end = match buf.iter().position(|&byte| byte == b'\n') {
+        Some(end) => end,
+        None if buf.len() > MAX_LINE_LEN => return Err(Reply::ClientError("line is too long")),
+        None => return Ok(None),
+    };
+    let line = buf[..end].strip_suffix(b"\r").unwrap_or(&buf[..end]);
+    let next = end + 1;
+
+    let mut args: Vec<&[u8]> = line
+        .split(|&byte| byte == b' ')
+        .filter(|arg| !arg.is_empty())
+        .collect();
+    if args.is_empty() {
+        return Ok(Some((Err(Reply::UnknownCommand), next)));
+    }
+    let name = args.remove(0);
+    if name == b"get" || name == b"gets" {
+        let parsed = get(&args).map(|command| Request {
+            command,
+            noreply: false,
+            cas: name == b"gets",
+        });
+        return Ok(Some((parsed, next)));
+    }
+
+    let noreply = args.last() == Some(&&b"noreply"[..]);
+    if noreply {
+        args.pop();
+    }
+    let command = match name {
+        b"set" | b"add" | b"replace" | b"cas" => return store(buf, next, name, &args, noreply),
+        b"delete" => match args[..] {
+            // Older clients send a hold time, which is only accepted as 0
+            [arg] | [arg, b"0"] => key(arg).map(|key| Command::Delete { key }),
+            [_, _] => Err(Reply::ClientError(
+                "bad command line format.  Usage: delete <key> [noreply]",
+            )),
+            _ => Err(Reply::UnknownCommand),
+        },
+        b"incr" | b"decr" => match args[..] {
+            [arg, delta] => counter(arg, delta, name == b"incr"),
+            _ => Err(Reply::UnknownCommand),
+        },
+        b"touch" => match args[..] {
+            [arg, exptime] => match number(exptime) {
+                Some(exptime) => key(arg).map(|key| Command::Touch {
+                    key,
+                    ttl: ttl_from_exptime(exptime),
+                }),
+                None => Err(Reply::ClientError("invalid exptime argument")),
+            },
+            _ => Err(Reply::UnknownCommand),
+        },
+        b"stats" if args.is_empty() => Ok(Command::Stats),
+        b"version" => Ok(Command::Version),
+        b"quit" => Ok(Command::Quit),
+        _ => Err(Reply::UnknownCommand),
+    };
+    let parsed = command.map(|command| Request {
+        command,
+        noreply,
+        cas: false,
+    });
+    Ok(Some((parsed, next)))
+}
+
+/// `get <key>*` and `gets <key>*`
+fn get(args: &[&[u8]]) -> Result<Command, Reply> {
+    if args.is_empty() {
+        return Err(Reply::UnknownCommand);
+    }
+    let keys = args.iter().map(|arg| key(arg)).collect::<Result<_, _>>()?;
+    Ok(Command::Get { keys })
+}
+
+/// `incr <key> <delta>` and `decr <key> <delta>`, which never create the
+/// counter.
+fn counter(arg: &[u8], delta: &[u8], increment: bool) -> Result<Command, Reply> {
+    let delta = number(delta).ok_or(Reply::ClientError("invalid numeric delta argument"))?;
+    Ok(Command::Counter {
+        key: key(arg)?,
+        delta,
+        increment,
+        initial: None,
+        ttl: None,
+    })
+}
+
+/// `<mode> <key> <flags> <exptime> <bytes>` or `cas <key> <flags> <exptime>
+/// <bytes> <cas unique>`, followed by a line holding the value.
+fn store(
+    buf: &[u8],
+    next: usize,
+    name: &[u8],
+    args: &[&[u8]],
+    noreply: bool,
+) -> Result<Option<(Parsed, usize)>, Reply> {
+    let (arg, flags, exptime, len, version) = match (name, args) {
+        (b"cas", [key, flags, exptime, len, version]) => (key, flags, exptime, len, Some(version)),
+        (b"cas", _) => return Ok(Some((Err(Reply::UnknownCommand), next))),
+        (_, [key, flags, exptime, len]) => (key, flags, exptime, len, None),
+        _ => return Ok(Some((Err(Reply::UnknownCommand), next))),
+    };
+    let bad_format = Err(Reply::ClientError("bad command line format"));
+    let (flags, exptime, len) = match (number(flags), number(exptime), number::<usize>(len)) {
+        (Some(flags), Some(exptime), Some(len)) => (flags, exptime, len),
+        _ => return Ok(Some((bad_format, next))),
+    };
+    let mode = match (name, version.map(|version| number(version))) {
+        (_, Some(Some(version))) => StoreMode::Cas(version),
+        (_, Some(None)) => return Ok(Some((bad_format, next))),
+        (b"add", None) => StoreMode::Add,
+        (b"replace", None) => StoreMode::Replace,
+        _ => StoreMode::Set,
+    };
+    if len > MAX_VALUE_LEN {
+        // The data is swallowed and the next commands served, like memcached
+        let end = next.saturating_add(len).saturating_add(2);
+        return Ok(Some((Err(Reply::TooLarge), end)));
+    }
+
+    let end = next + len + 2;
+    if buf.len() < end {
+        return Ok(None);
+    }
+    if &buf[next + len..end] != b"\r\n" {
+        return Ok(Some((Err(Reply::ClientError("bad data chunk")), end)));
+    }
+    let parsed = key(arg).map(|key| Request {
+        command: Command::Store {
+            mode,
+            key,
+            flags,
+            ttl: ttl_from_exptime(exptime),
+            value: Bytes::copy_from_slice(&buf[next..next + len]),
+        },
+        noreply,
+        cas: false,
+    });
+    Ok(Some((parsed, end)))
+}
+
+fn write_line(out: &mut Vec<u8>, line: &[u8]) {
+    out.extend_from_slice(line);
+    out.extend_from_slice(b"\r\n");
+}
+
+/// Writes the reply, along with the versions of the values if `cas` is set.
+pub fn write_reply(reply: &Reply, cas: bool, out: &mut Vec<u8>) {
+    match reply {
+        Reply::Values(values) => {
+            for (key, value) in values {
+                out.extend_from_slice(b"VALUE ");
+                out.extend_from_slice(key);
+                let _ = write!(out, " {} {}", value.flags, value.value.len());
+                if cas {
+                    let _ = write!(out, " {}", value.version);
+                }
+                out.extend_from_slice(b"\r\n");
+                write_line(out, &value.value);
+            }
+            write_line(out, b"END");
+        }
+        Reply::Stored(_) => write_line(out, b"STORED"),
+        Reply::NotStored => write_line(out, b"NOT_STORED"),
+        Reply::Exists => write_line(out, b"EXISTS"),
+        Reply::NotFound => write_line(out, b"NOT_FOUND"),
+        Reply::Deleted => write_line(out, b"DELETED"),
+        Reply::Touched => write_line(out, b"TOUCHED"),
+        Reply::Counter { value, .. } => write_line(out, value.to_string().as_bytes()),
+        Reply::NonNumeric => write_line(
+            out,
+            b"CLIENT_ERROR cannot increment or decrement non-numeric value",
+        ),
+        Reply::TooLarge => write_line(out, b"SERVER_ERROR object too large for cache"),
+        Reply::Stats(stats) => {
+            for (name, value) in stats {
+                let _ = write!(out, "STAT {name} {value}\r\n");
+            }
+            write_line(out, b"END");
+        }
+        Reply::Version => {
+            let _ = write!(out, "VERSION {}\r\n", env!("CARGO_PKG_VERSION"));
+        }
+        Reply::Done => {}
+        Reply::UnknownCommand => write_line(out, b"ERROR"),
+        Reply::ClientError(msg) => {
+            let _ = write!(out, "CLIENT_ERROR {msg}\r\n");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::Versioned;
+    use std::time::Duration;
+
+    fn request(input: &[u8]) -> (Request, usize) {
+        match parse(input) {
+            Ok(Some((Ok(request), taken))) => (request, taken),
+            _ => panic!("expected a command"),
+        }
+    }
+
+    #[test]
+    fn waits_for_the_whole_command() {
+        let input = b"set k 5 0 3 noreply\r\nabc\r\n";
+        for end in 0..input.len() {
+            assert!(matches!(parse(&input[..end]), Ok(None)));
+        }
+        let (request, taken) = request(input);
+        assert_eq!(taken, input.len());
+        assert!(request.noreply);
+        match request.command {
+            Command::Store {
+                mode,
+                key,
+                flags,
+                ttl,
+                value,
+            } => {
+                assert!(mode == StoreMode::Set);
+                assert_eq!(
+                    (&key[..], flags, ttl, &value[..]),
+                    (&b"k"[..], 5, None, &b"abc"[..])
+                );
+            }
+            _ => panic!("expected a store"),
+        }
+    }
+
+    #[test]
+    fn parses_pipelined_commands() {
+        let input = b"gets a b\r\ncas k 0 10 1 7\r\nx\r\ndelete k 0\nincr n 2\r\nstats\r\n";
+        let mut buf = &input[..];
+        let (request, taken) = self::request(buf);
+        assert!(request.cas && !request.noreply);
+        assert!(matches!(request.command, Command::Get { keys } if keys == ["a", "b"]));
+        buf = &buf[taken..];
+        let (request, taken) = self::request(buf);
+        assert!(matches!(
+            request.command,
+            Command::Store { mode: StoreMode::Cas(7), ttl: Some(ttl), .. }
+                if ttl == Duration::from_secs(10)
+        ));
+        buf = &buf[taken..];
+        let (request, taken) = self::request(buf);
+        assert!(matches!(request.command, Command::Delete { key } if key == "k"));
+        buf = &buf[taken..];
+        let (request, taken) = self::request(buf);
+        assert!(matches!(
+            request.command,
+            Command::Counter {
+                delta: 2,
+                increment: true,
+                initial: None,
+                ..
+            }
+        ));
+        buf = &buf[taken..];
+        let (request, taken) = self::request(buf);
+        assert!(matches!(request.command, Command::Stats));
+        assert_eq!(taken, buf.len());
+    }
+
+    #[test]
+    fn answers_malformed_commands() {
+        let reply = |input: &[u8]| match parse(input) {
+            Ok(Some((Err(reply), taken))) => (reply, taken),
+            _ => panic!("expected an error reply"),
+        };
+        assert!(matches!(reply(b"\r\n"), (Reply::UnknownCommand, 2)));
+        assert!(matches!(
+            reply(b"frobnicate\r\n"),
+            (Reply::UnknownCommand, 12)
+        ));
+        assert!(matches!(
+            reply(b"delete k 10\r\n"),
+            (Reply::ClientError(_), 13)
+        ));
+        assert!(matches!(
+            reply(b"set k x 0 1\r\n"),
+            (Reply::ClientError(_), 13)
+        ));
+        assert!(matches!(
+            reply(b"set k 0 0 1\r\nab\r\n"),
+            (Reply::ClientError(_), 16)
+        ));
+        let long_key = format!("get {}\r\n", "k".repeat(MAX_KEY_LEN + 1));
+        assert!(matches!(
+            reply(long_key.as_bytes()),
+            (Reply::ClientError(_), _)
+        ));
+    }
+
+    #[test]
+    fn swallows_values_too_large() {
+        let input = format!("set k 0 0 {}\r\nab", MAX_VALUE_LEN + 1);
+        let line = input.len() - 2;
+        match parse(input.as_bytes()) {
+            Ok(Some((Err(Reply::TooLarge), taken))) => {
+                assert_eq!(taken, line + MAX_VALUE_LEN + 1 + 2)
+            }
+            _ => panic!("expected the value to be too large"),
+        }
+    }
+
+    #[test]
+    fn rejects_lines_too_long() {
+        let line = vec![b'a'; MAX_LINE_LEN];
+        assert!(matches!(parse(&line), Ok(None)));
+        let line = vec![b'a'; MAX_LINE_LEN + 1];
+        assert!(matches!(parse(&line), Err(Reply::ClientError(_))));
+    }
+
+    #[test]
+    fn writes_values_with_versions() {
+        let value = Versioned {
+            value: Bytes::from_static(b"abc"),
+            flags: 3,
+            version: 9,
+        };
+        let reply = Reply::Values(vec![(Bytes::from_static(b"k"), value)]);
+        let mut out = vec![];
+        write_reply(&reply, false, &mut out);
+        assert_eq!(out, b"VALUE k 3 3\r\nabc\r\nEND\r\n");
+        out.clear();
+        write_reply(&reply, true, &mut out);
+        assert_eq!(out, b"VALUE k 3 3 9\r\nabc\r\nEND\r\n");
+    }
+}
@@ -1,5 +1,8 @@
 pub mod bindings;
+pub mod count_min_sketch;
 pub mod doubly_linked_list;
 pub mod gen_arena;
 pub mod hash;
 pub mod http;
+pub mod memcached;
+pub mod resp;
@@ -0,0 +1,353 @@
+use super::{Frame, Parser, Protocol};
+use crate::{
+    cache::{Cache, Versioned},
+    rpc::{CounterRequest, Entry, Key, Value, WriteMode},
+    CacheServer, RESPServer,
+};
+use bytes::{Bytes, BytesMut};
+use std::{
+    io,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use tonic::Code;
+
+/// State of a client connection.
+struct Session {
+    protocol: Protocol,
+    closing: bool,
+}
+
+fn wrong_arity(command: &str) -> Frame {
+    Frame::error(format!(
+        "ERR wrong number of arguments for '{}' command",
+        command.to_ascii_lowercase()
+    ))
+}
+
+fn not_an_integer() -> Frame {
+    Frame::error("ERR value is not an integer or out of range")
+}
+
+fn syntax_error() -> Frame {
+    Frame::error("ERR syntax error")
+}
+
+fn int_arg(arg: &[u8]) -> Result<i64, Frame> {
+    std::str::from_utf8(arg)
+        .ok()
+        .and_then(|arg| arg.parse().ok())
+        .ok_or_else(not_an_integer)
+}
+
+/// Serves the commands of a client until it disconnects or quits.
+pub(crate) async fn serve<C>(
+    server: Arc<CacheServer<C, RESPServer>>,
+    mut socket: TcpStream,
+) -> io::Result<()>
+where
+    C: Cache<Vec<u8>, Versioned<Bytes>> + Send + Sync + 'static,
+{
+    let mut session = Session {
+        protocol: Protocol::Resp2,
+        closing: false,
+    };
+    let mut parser = Parser::default();
+    let mut buf = BytesMut::with_capacity(4096);
+    let mut out = Vec::new();
+    loop {
+        // Answer every pipelined command already read before writing back
+        loop {
+            match parser.parse(&mut buf) {
+                Ok(Some(frame)) => {
+                    let reply = execute(&server, &mut session, frame).await;
+                    reply.write_to(session.protocol, &mut out);
+                    if session.closing {
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(msg) => {
+                    Frame::error(format!("ERR Protocol error: {msg}"))
+                        .write_to(session.protocol, &mut out);
+                    session.closing = true;
+                    break;
+                }
+            }
+        }
+        if !out.is_empty() {
+            socket.write_all(&out).await?;
+            out.clear();
+        }
+        if session.closing || socket.read_buf(&mut buf).await? == 0 {
+            return Ok(());
+        }
+    }
+}
+
+async fn execute<C>(
+    server: &CacheServer<C, RESPServer>,
+    session: &mut Session,
+    frame: Frame,
+) -> Frame
+where
+    C: Cache<Vec<u8>, Versioned<Bytes>> + Send + Sync + 'static,
+{
+    let mut args = vec![];
+    match frame {
+        Frame::Array(frames) => {
+            for frame in frames {
+                match frame {
+                    Frame::Bulk(arg) => args.push(arg),
+                    Frame::Simple(arg) => args.push(Bytes::from(arg)),
+                    _ => return Frame::error("ERR Protocol error: expected a bulk string"),
+                }
+            }
+        }
+        _ => return Frame::error("ERR Protocol error: expected an array"),
+    }
+    let (name, args) = match args.split_first() {
+        Some((name, args)) => (String::from_utf8_lossy(name).into_owned(), args),
+        // Never given by the parser, which skips the empty commands
+        None => return Frame::error("ERR Protocol error: expected a command"),
+    };
+    let command = name.to_ascii_uppercase();
+
+    let result = match command.as_str() {
+        "PING" => ping(args),
+        "HELLO" => hello(session, args),
+        "QUIT" => {
+            session.closing = true;
+            Ok(Frame::ok())
+        }
+        // Clients such as `redis-cli` ask for the command docs on startup
+        "COMMAND" => Ok(Frame::Array(vec![])),
+        "GET" => get(server, args).await,
+        "MGET" => mget(server, args).await,
+        "SET" => set(server, args).await,
+        "DEL" => del(server, args).await,
+        "EXPIRE" => expire(server, args).await,
+        "TTL" => ttl(server, args).await,
+        "INCR" | "DECR" if args.len() != 1 => Err(wrong_arity(&command)),
+        "INCR" => count(server, &args[0], 1).await,
+        "DECR" => count(server, &args[0], -1).await,
+        "INCRBY" | "DECRBY" if args.len() != 2 => Err(wrong_arity(&command)),
+        "INCRBY" => match int_arg(&args[1]) {
+            Ok(delta) => count(server, &args[0], delta).await,
+            Err(err) => Err(err),
+        },
+        "DECRBY" => match int_arg(&args[1]).map(i64::checked_neg) {
+            Ok(Some(delta)) => count(server, &args[0], delta).await,
+            Ok(None) => Err(not_an_integer()),
+            Err(err) => Err(err),
+        },
+        _ => Err(Frame::error(format!("ERR unknown command '{name}'"))),
+    };
+    result.unwrap_or_else(|err| err)
+}
+
+fn ping(args: &[Bytes]) -> Result<Frame, Frame> {
+    match args {
+        [] => Ok(Frame::Simple("PONG".to_string())),
+        [message] => Ok(Frame::Bulk(message.clone())),
+        _ => Err(wrong_arity("ping")),
+    }
+}
+
+/// Switches the protocol version and describes the server.
+fn hello(session: &mut Session, args: &[Bytes]) -> Result<Frame, Frame> {
+    if let Some(version) = args.first() {
+        session.protocol = match int_arg(version) {
+            Ok(2) => Protocol::Resp2,
+            Ok(3) => Protocol::Resp3,
+            _ => return Err(Frame::error("NOPROTO unsupported protocol version")),
+        };
+    }
+    let version = match session.protocol {
+        Protocol::Resp2 => 2,
+        Protocol::Resp3 => 3,
+    };
+    let field = |name: &str| Frame::Bulk(Bytes::copy_from_slice(name.as_bytes()));
+    Ok(Frame::Map(vec![
+        (field("server"), field("discache")),
+        (field("version"), field(env!("CARGO_PKG_VERSION"))),
+        (field("proto"), Frame::Integer(version)),
+        (field("mode"), field("standalone")),
+        (field("role"), field("master")),
+        (field("modules"), Frame::Array(vec![])),
+    ]))
+}
+
+async fn get<C>(server: &CacheServer<C, RESPServer>, args: &[Bytes]) -> Result<Frame, Frame>
+where
+    C: Cache<Vec<u8>, Versioned<Bytes>> + Send + Sync + 'static,
+{
+    match args {
+        [key] => Ok(match server.lookup(&key.to_vec()).await {
+            Some((value, _)) => Frame::Bulk(value.value),
+            None => Frame::Null,
+        }),
+        _ => Err(wrong_arity("get")),
+    }
+}
+
+async fn mget<C>(server: &CacheServer<C, RESPServer>, args: &[Bytes]) -> Result<Frame, Frame>
+where
+    C: Cache<Vec<u8>, Versioned<Bytes>> + Send + Sync + 'static,
+{
+    if args.is_empty() {
+        return Err(wrong_arity("mget"));
+    }
+    let mut values = Vec::with_capacity(args.len());
+    for key in args {
+        values.push(match server.lookup(&key.to_vec()).await {
+            Some((value, _)) => Frame::Bulk(value.value),
+            None => Frame::Null,
+        });
+    }
+    Ok(Frame::Array(values))
+}
+
+/// `SET key value [NX | XX] [EX seconds | PX milliseconds]`
+async fn set<C>(server: &CacheServer<C, RESPServer>, args: &[Bytes]) -> Result<Frame, Frame>
+where
+    C: Cache<Vec<u8>, Versioned<Bytes>> + Send + Sync + 'static,
+{
+    let (key, value, options) = match args {
+        [key, value, options @ ..] => (key, value, options),
+        _ => return Err(wrong_arity("set")),
+    };
+    let mut mode = WriteMode::Set;
+    let mut ttl = 0;
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match option.to_ascii_uppercase().as_slice() {
+            b"NX" if mode == WriteMode::Set => mode = WriteMode::Add,
+            b"XX" if mode == WriteMode::Set => mode = WriteMode::Replace,
+            unit @ (b"EX" | b"PX") if ttl == 0 => {
+                let amount = options.next().ok_or_else(syntax_error)?;
+                let amount = int_arg(amount)?;
+                let millis = if unit == b"EX" {
+                    amount.checked_mul(1000)
+                } else {
+                    Some(amount)
+                };
+                ttl = match millis {
+                    Some(millis) if millis > 0 => millis as u64,
+                    _ => return Err(Frame::error("ERR invalid expire time in 'set' command")),
+                };
+            }
+            _ => return Err(syntax_error()),
+        }
+    }
+
+    let entry = Entry {
+        key: Some(Key { key: key.to_vec() }),
+        value: Some(Value {
+            value: value.clone(),
+        }),
+        ttl,
+        mode: mode.into(),
+        version: 0,
+    };
+    match server.put_entry(entry).await {
+        Ok(_) => Ok(Frame::ok()),
+        // The condition of NX or XX does not hold
+        Err(status) if matches!(status.code(), Code::AlreadyExists | Code::NotFound) => {
+            Ok(Frame::Null)
+        }
+        Err(status) => Err(Frame::error(format!("ERR {}", status.message()))),
+    }
+}
+
+async fn del<C>(server: &CacheServer<C, RESPServer>, args: &[Bytes]) -> Result<Frame, Frame>
+where
+    C: Cache<Vec<u8>, Versioned<Bytes>> + Send + Sync + 'static,
+{
+    if args.is_empty() {
+        return Err(wrong_arity("del"));
+    }
+    let mut deleted = 0;
+    for key in args {
+        if server.delete_entry(key.to_vec()).await.is_some() {
+            deleted += 1;
+        }
+    }
+    Ok(Frame::Integer(deleted))
+}
+
+/// `EXPIRE key seconds`, where a time to live that is not positive deletes
+/// the key right away.
+async fn expire<C>(server: &CacheServer<C, RESPServer>, args: &[Bytes]) -> Result<Frame, Frame>
+where
+    C: Cache<Vec<u8>, Versioned<Bytes>> + Send + Sync + 'static,
+{
+    let (key, seconds) = match args {
+        [key, seconds] => (key.to_vec(), int_arg(seconds)?),
+        _ => return Err(wrong_arity("expire")),
+    };
+    let present = if seconds > 0 {
+        // Same bound as Redis, which keeps the expiry in milliseconds
+        if seconds.checked_mul(1000).is_none() {
+            return Err(Frame::error("ERR invalid expire time in 'expire' command"));
+        }
+        server
+            .touch(key, Some(Duration::from_secs(seconds as u64)))
+            .await
+    } else {
+        server.delete_entry(key).await.is_some()
+    };
+    Ok(Frame::Integer(present.into()))
+}
+
+/// `TTL key`, which replies `-2` if the key is not present and `-1` if it
+/// never expires.
+async fn ttl<C>(server: &CacheServer<C, RESPServer>, args: &[Bytes]) -> Result<Frame, Frame>
+where
+    C: Cache<Vec<u8>, Versioned<Bytes>> + Send + Sync + 'static,
+{
+    let key = match args {
+        [key] => key.to_vec(),
+        _ => return Err(wrong_arity("ttl")),
+    };
+    // Only reads the entry, so that asking for its TTL is not an access
+    let cache = server.cache.shard(&key).read().await;
+    if !cache.contains_key(&key) {
+        return Ok(Frame::Integer(-2));
+    }
+    Ok(Frame::Integer(match cache.expires_at(&key) {
+        Some(expires_at) => {
+            let remaining = expires_at.saturating_duration_since(Instant::now());
+            // Rounded to the closest second, like Redis does
+            ((remaining.as_millis() + 500) / 1000) as i64
+        }
+        None => -1,
+    }))
+}
+
+async fn count<C>(
+    server: &CacheServer<C, RESPServer>,
+    key: &Bytes,
+    delta: i64,
+) -> Result<Frame, Frame>
+where
+    C: Cache<Vec<u8>, Versioned<Bytes>> + Send + Sync + 'static,
+{
+    let request = CounterRequest {
+        key: Some(Key { key: key.to_vec() }),
+        delta: delta.unsigned_abs(),
+        initial: 0,
+        ttl: 0,
+    };
+    match server.count(request, delta >= 0).await {
+        Ok(response) => Ok(Frame::Integer(response.into_inner().value)),
+        Err(status) if matches!(status.code(), Code::FailedPrecondition | Code::OutOfRange) => {
+            Err(not_an_integer())
+        }
+        Err(status) => Err(Frame::error(format!("ERR {}", status.message()))),
+    }
+}
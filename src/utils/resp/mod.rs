@@ -0,0 +1,319 @@
+pub mod cache;
+
+use bytes::{Buf, Bytes, BytesMut};
+
+/// Largest bulk string a client may send, same as the default of Redis.
+const MAX_BULK_LEN: usize = 512 << 20;
+
+/// Largest number of elements of an array a client may send.
+const MAX_ARRAY_LEN: usize = 1 << 20;
+
+/// Longest line a client may send, e.g. an inline command or the header of
+/// a bulk string, same as Redis.
+const MAX_LINE_LEN: usize = 64 << 10;
+
+/// Version of the REdis Serialization Protocol spoken on a connection, which
+/// a client can switch with the `HELLO` command.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Protocol {
+    Resp2,
+    Resp3,
+}
+
+/// A value of the REdis Serialization Protocol.
+#[derive(Debug)]
+pub enum Frame {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Bytes),
+    Array(Vec<Frame>),
+    Null,
+    /// Written as a flat array of the keys and values in RESP2
+    Map(Vec<(Frame, Frame)>),
+}
+
+impl Frame {
+    pub fn ok() -> Self {
+        Frame::Simple("OK".to_string())
+    }
+
+    pub fn error(msg: impl Into<String>) -> Self {
+        Frame::Error(msg.into())
+    }
+
+    /// Writes the frame in the given protocol version.
+    pub fn write_to(&self, protocol: Protocol, out: &mut Vec<u8>) {
+        match self {
+            Frame::Simple(string) => write_line(out, b'+', string.as_bytes()),
+            Frame::Error(msg) => write_line(out, b'-', msg.as_bytes()),
+            Frame::Integer(int) => write_line(out, b':', int.to_string().as_bytes()),
+            Frame::Bulk(data) => {
+                write_line(out, b'$', data.len().to_string().as_bytes());
+                out.extend_from_slice(data);
+                out.extend_from_slice(b"\r\n");
+            }
+            Frame::Array(frames) => {
+                write_line(out, b'*', frames.len().to_string().as_bytes());
+                for frame in frames {
+                    frame.write_to(protocol, out);
+                }
+            }
+            Frame::Null => match protocol {
+                Protocol::Resp2 => out.extend_from_slice(b"$-1\r\n"),
+                Protocol::Resp3 => out.extend_from_slice(b"_\r\n"),
+            },
+            Frame::Map(pairs) => {
+                match protocol {
+                    Protocol::Resp2 => {
+                        write_line(out, b'*', (2 * pairs.len()).to_string().as_bytes())
+                    }
+                    Protocol::Resp3 => write_line(out, b'%', pairs.len().to_string().as_bytes()),
+                }
+                for (key, value) in pairs {
+                    key.write_to(protocol, out);
+                    value.write_to(protocol, out);
+                }
+            }
+        }
+    }
+}
+
+fn write_line(out: &mut Vec<u8>, prefix: u8, line: &[u8]) {
+    out.push(prefix);
+    out.extend_from_slice(line);
+    out.extend_from_slice(b"\r\n");
+}
+
+/// Returns the line at the start of the buffer and the number of bytes it
+/// takes with its `\r\n`, or `None` if the line is not complete yet.
+fn read_line(buf: &[u8]) -> Result<Option<(&[u8], usize)>, &'static str> {
+    let searched = &buf[..buf.len().min(MAX_LINE_LEN + 2)];
+    match searched.windows(2).position(|window| window == b"\r\n") {
+        Some(len) => Ok(Some((&buf[..len], len + 2))),
+        None if buf.len() <= MAX_LINE_LEN => Ok(None),
+        None => Err("too big line"),
+    }
+}
+
+fn parse_int(line: &[u8]) -> Result<i64, &'static str> {
+    std::str::from_utf8(line)
+        .ok()
+        .and_then(|line| line.parse().ok())
+        .ok_or("invalid integer")
+}
+
+/// Piece of a frame parsed from the start of the buffer.
+enum Token {
+    Frame(Frame),
+    /// Header of an array, followed by the given number of elements
+    Array(usize),
+    /// Line without any command to execute, e.g. an empty one
+    Skip,
+}
+
+/// Parses a token from the start of the buffer, and returns it along with
+/// the number of bytes it took, or `None` if the buffer does not hold a whole
+/// token yet. Lines not starting with a type prefix are parsed as inline
+/// commands, i.e. arguments separated by spaces, as typed in a telnet session,
+/// unless they are `nested` in an array.
+fn parse_token(buf: &[u8], nested: bool) -> Result<Option<(Token, usize)>, &'static str> {
+    let (line, next) = match read_line(buf)? {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+    let (prefix, rest) = match line.split_first() {
+        Some(split) => split,
+        // Empty lines are ignored without any reply, like Redis does
+        None if !nested => return Ok(Some((Token::Skip, next))),
+        None => return Err("expected a frame"),
+    };
+    let token = match prefix {
+        b'+' => Token::Frame(Frame::Simple(String::from_utf8_lossy(rest).into_owned())),
+        b':' => Token::Frame(Frame::Integer(parse_int(rest)?)),
+        b'$' => {
+            let len = parse_int(rest)?;
+            if len < 0 {
+                return Ok(Some((Token::Frame(Frame::Null), next)));
+            }
+            let len = len as usize;
+            if len > MAX_BULK_LEN {
+                return Err("invalid bulk length");
+            }
+            if buf.len() < next + len + 2 {
+                return Ok(None);
+            }
+            if &buf[next + len..next + len + 2] != b"\r\n" {
+                return Err("bulk string is not terminated by CRLF");
+            }
+            let data = Bytes::copy_from_slice(&buf[next..next + len]);
+            return Ok(Some((Token::Frame(Frame::Bulk(data)), next + len + 2)));
+        }
+        b'*' => {
+            let len = parse_int(rest)?;
+            if len > MAX_ARRAY_LEN as i64 {
+                return Err("invalid multibulk length");
+            }
+            match len {
+                ..=-1 if nested => Token::Frame(Frame::Null),
+                0 if nested => Token::Frame(Frame::Array(vec![])),
+                // Empty commands are ignored as well
+                ..=0 => Token::Skip,
+                len => Token::Array(len as usize),
+            }
+        }
+        _ if nested => return Err("expected a frame"),
+        _ => {
+            let args: Vec<_> = line
+                .split(|byte| byte.is_ascii_whitespace())
+                .filter(|arg| !arg.is_empty())
+                .map(|arg| Frame::Bulk(Bytes::copy_from_slice(arg)))
+                .collect();
+            if args.is_empty() {
+                Token::Skip
+            } else {
+                Token::Frame(Frame::Array(args))
+            }
+        }
+    };
+    Ok(Some((token, next)))
+}
+
+/// Parses the frames sent over a connection as their bytes arrive. The
+/// elements of the arrays are kept as they get parsed, so that the bytes of
+/// an array arriving over many reads are only parsed once.
+#[derive(Default)]
+pub struct Parser {
+    /// Arrays being parsed, innermost last, along with the number of elements
+    /// each of them still misses
+    arrays: Vec<(Vec<Frame>, usize)>,
+}
+
+impl Parser {
+    /// Parses the next frame out of the buffer, consuming the bytes it took,
+    /// or returns `None` if the buffer does not hold the rest of a frame yet.
+    pub fn parse(&mut self, buf: &mut BytesMut) -> Result<Option<Frame>, &'static str> {
+        loop {
+            let (token, len) = match parse_token(buf, !self.arrays.is_empty())? {
+                Some(token) => token,
+                None => return Ok(None),
+            };
+            buf.advance(len);
+            let mut frame = match token {
+                Token::Frame(frame) => frame,
+                Token::Array(len) => {
+                    // The length is only trusted as far as the elements arrive
+                    self.arrays.push((Vec::with_capacity(len.min(1024)), len));
+                    continue;
+                }
+                Token::Skip => continue,
+            };
+            // Completes every array the frame is the last element of
+            loop {
+                match self.arrays.last_mut() {
+                    Some((frames, missing)) => {
+                        frames.push(frame);
+                        *missing -= 1;
+                        if *missing > 0 {
+                            break;
+                        }
+                    }
+                    None => return Ok(Some(frame)),
+                }
+                let (frames, _) = self.arrays.pop().unwrap();
+                frame = Frame::Array(frames);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(frame: &Frame) -> Vec<u8> {
+        let mut out = vec![];
+        frame.write_to(Protocol::Resp3, &mut out);
+        out
+    }
+
+    fn parse_all(parser: &mut Parser, buf: &mut BytesMut) -> Vec<Vec<u8>> {
+        let mut frames = vec![];
+        while let Some(frame) = parser.parse(buf).unwrap() {
+            frames.push(encode(&frame));
+        }
+        frames
+    }
+
+    #[test]
+    fn parses_a_frame_arriving_byte_by_byte() {
+        let command = b"*3\r\n$3\r\nSET\r\n*2\r\n:1\r\n$-1\r\n+OK\r\n";
+        let mut parser = Parser::default();
+        let mut buf = BytesMut::new();
+        for (i, byte) in command.iter().enumerate() {
+            buf.extend_from_slice(&[*byte]);
+            let frame = parser.parse(&mut buf).unwrap();
+            assert_eq!(frame.is_some(), i == command.len() - 1);
+            if let Some(frame) = frame {
+                assert_eq!(
+                    encode(&frame),
+                    b"*3\r\n$3\r\nSET\r\n*2\r\n:1\r\n_\r\n+OK\r\n"
+                );
+            }
+        }
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn parses_pipelined_frames() {
+        let mut parser = Parser::default();
+        let mut buf =
+            BytesMut::from(&b"*1\r\n$4\r\nPING\r\n\r\n*0\r\nECHO  a b\r\n*2\r\n$3\r\nGET"[..]);
+        let frames = parse_all(&mut parser, &mut buf);
+        // The empty line and array are skipped, the last frame is incomplete
+        assert_eq!(
+            frames,
+            [
+                b"*1\r\n$4\r\nPING\r\n".to_vec(),
+                b"*3\r\n$4\r\nECHO\r\n$1\r\na\r\n$1\r\nb\r\n".to_vec(),
+            ]
+        );
+        buf.extend_from_slice(b"\r\n$1\r\nk\r\n");
+        assert_eq!(
+            parse_all(&mut parser, &mut buf),
+            [b"*2\r\n$3\r\nGET\r\n$1\r\nk\r\n".to_vec()]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_frames() {
+        let parse = |bytes: &[u8]| Parser::default().parse(&mut BytesMut::from(bytes));
+        assert_eq!(parse(b"*1\r\nPING\r\n").unwrap_err(), "expected a frame");
+        assert_eq!(parse(b"*1\r\n\r\n").unwrap_err(), "expected a frame");
+        assert_eq!(
+            parse(b"$2\r\nabc\r\n").unwrap_err(),
+            "bulk string is not terminated by CRLF"
+        );
+        assert_eq!(parse(b"*x\r\n").unwrap_err(), "invalid integer");
+        assert_eq!(
+            parse(b"*2000000\r\n").unwrap_err(),
+            "invalid multibulk length"
+        );
+        assert_eq!(parse(b"$600000000\r\n").unwrap_err(), "invalid bulk length");
+        // A line is only waited for up to the longest one allowed
+        assert!(parse(&vec![b'a'; MAX_LINE_LEN]).unwrap().is_none());
+        assert_eq!(
+            parse(&vec![b'a'; MAX_LINE_LEN + 3]).unwrap_err(),
+            "too big line"
+        );
+    }
+
+    #[test]
+    fn writes_maps_and_nulls_per_protocol() {
+        let map = Frame::Map(vec![(Frame::Simple("a".into()), Frame::Null)]);
+        let mut out = vec![];
+        map.write_to(Protocol::Resp2, &mut out);
+        assert_eq!(out, b"*2\r\n+a\r\n$-1\r\n");
+        assert_eq!(encode(&map), b"%1\r\n+a\r\n_\r\n");
+    }
+}